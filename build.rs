@@ -0,0 +1,19 @@
+// Sets `fixed_heap` cfg if the heap is located at a fixed address, i.e. when
+// building for Solana or when building for Linux with `sbf-memory-map` Cargo
+// feature (unless `testing` feature is enabled as well).
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(fixed_heap)");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let is_os = |name| os.split(',').any(|os| os == name);
+    let feature = |name| std::env::var_os(name).is_some();
+    if is_os("solana") ||
+        (is_os("linux") &&
+            feature("CARGO_FEATURE_SBF_MEMORY_MAP") &&
+            !feature("CARGO_FEATURE_TESTING"))
+    {
+        println!("cargo:rustc-cfg=fixed_heap");
+    }
+}
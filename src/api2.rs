@@ -0,0 +1,177 @@
+//! Implementation of `allocator_api2::alloc::Allocator` for the allocators.
+//!
+//! This lets allocators be used with `allocator_api2` collections.  The main
+//! use case is checking heap usage in unit tests with allocators created by
+//! `with_capacity` (see `testing` Cargo feature) but the implementation works
+//! on Solana as well.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use allocator_api2::alloc::{AllocError, Allocator};
+
+use crate::{BumpAllocator, FreeListAllocator};
+
+unsafe impl<G: bytemuck::Zeroable> Allocator for &BumpAllocator<G> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate(*self, layout, false)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        allocate(*self, layout, true)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Caller guarantees ptr has been allocated with layout.
+        unsafe { deallocate(*self, ptr, layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { reallocate(*self, ptr, old, new) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { reallocate(*self, ptr, old, new) }
+    }
+}
+
+unsafe impl<G: bytemuck::Zeroable> Allocator for &FreeListAllocator<G> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate(*self, layout, false)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        allocate(*self, layout, true)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Caller guarantees ptr has been allocated with layout.
+        unsafe { deallocate(*self, ptr, layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { reallocate(*self, ptr, old, new) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { reallocate(*self, ptr, old, new) }
+    }
+}
+
+/// Allocates memory with `GlobalAlloc` interface.
+///
+/// Like with the standard allocators, zero-sized blocks don’t take any memory
+/// and are represented by a dangling pointer.  Collections such as
+/// `allocator_api2::boxed::Box` rely on this and free dangling pointers they
+/// never allocated.
+fn allocate(
+    alloc: &impl GlobalAlloc,
+    layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if layout.size() == 0 {
+        return Ok(NonNull::slice_from_raw_parts(dangling(layout), 0));
+    }
+    // SAFETY: Layout has non-zero size.
+    let ptr = unsafe {
+        if zeroed {
+            alloc.alloc_zeroed(layout)
+        } else {
+            alloc.alloc(layout)
+        }
+    };
+    let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+}
+
+/// Frees memory block with `GlobalAlloc` interface.  Zero-sized blocks
+/// haven’t been allocated so there’s nothing to free.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated by [`allocate`] with `layout`.
+unsafe fn deallocate(
+    alloc: &impl GlobalAlloc,
+    ptr: NonNull<u8>,
+    layout: Layout,
+) {
+    if layout.size() != 0 {
+        // SAFETY: Caller guarantees ptr has been allocated with layout.
+        unsafe { alloc.dealloc(ptr.as_ptr(), layout) }
+    }
+}
+
+/// Returns dangling pointer aligned for `layout`.
+fn dangling(layout: Layout) -> NonNull<u8> {
+    // SAFETY: Alignment is never zero.
+    unsafe {
+        NonNull::new_unchecked(
+            core::ptr::null_mut::<u8>().wrapping_add(layout.align()),
+        )
+    }
+}
+
+/// Grows or shrinks memory block with `GlobalAlloc` interface.
+///
+/// Uses `realloc` (which may resize the block in place) if alignment doesn’t
+/// change and neither of the blocks is zero-sized.  Otherwise allocates a new
+/// block and copies the data.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated by [`allocate`] with `old` layout.
+unsafe fn reallocate(
+    alloc: &impl GlobalAlloc,
+    ptr: NonNull<u8>,
+    old: Layout,
+    new: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let in_place =
+        old.align() == new.align() && old.size() != 0 && new.size() != 0;
+    let new_ptr = if in_place {
+        // SAFETY: Caller guarantees ptr has been allocated with old layout.
+        unsafe { alloc.realloc(ptr.as_ptr(), old, new.size()) }
+    } else {
+        let new_ptr = allocate(alloc, new, false)?.cast::<u8>().as_ptr();
+        // SAFETY: Both regions are valid for the smaller of the sizes and
+        // don’t overlap since ptr is still allocated.
+        unsafe {
+            let size = old.size().min(new.size());
+            core::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr, size);
+            deallocate(alloc, ptr, old);
+        }
+        new_ptr
+    };
+    let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+    Ok(NonNull::slice_from_raw_parts(new_ptr, new.size()))
+}
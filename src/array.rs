@@ -0,0 +1,419 @@
+//! Fixed-capacity containers which can be used as fields of the global state.
+//!
+//! Zeroed `Vec` or `String` is not a valid object thus they can’t be stored in
+//! global state declared with [`custom_global`](`crate::custom_global`).  This
+//! module provides [`ArrayVec`], [`ArrayString`] and [`ArrayMap`] types which
+//! store their elements inline and are [`bytemuck::Zeroable`] with zeroed
+//! object being an empty container.
+//!
+//! To be modified through shared reference returned by the global state
+//! accessor, the containers need to be wrapped in
+//! [`RefCell`](`crate::cell::RefCell`).  Alternatively, global state can be
+//! declared with `mut` prefix to be accessed through a mutable reference.
+//!
+//! # Example
+//!
+//! ```ignore
+//! solana_allocator::custom_global!(struct GlobalData {
+//!     signers: RefCell<ArrayVec<Pubkey, 8>>,
+//!     memo: RefCell<ArrayString<64>>,
+//! });
+//!
+//! fn add_signer(key: Pubkey) {
+//!     global().signers.borrow_mut().push(key).expect("too many signers");
+//! }
+//! ```
+
+use core::fmt;
+use core::mem::MaybeUninit;
+
+#[cfg(test)]
+mod tests;
+
+/// Error returned when a container has no space for new data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityError;
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("insufficient capacity")
+    }
+}
+
+impl core::error::Error for CapacityError {}
+
+
+/// Vector with fixed capacity storing its elements inline.
+///
+/// [`bytemuck::Zeroable`] for any `T` with zeroed object being an empty
+/// vector.  Dereferences to a slice of its elements.
+pub struct ArrayVec<T, const N: usize> {
+    len: usize,
+    data: [MaybeUninit<T>; N],
+}
+
+// SAFETY: Zero length means no elements are initialised.
+unsafe impl<T, const N: usize> bytemuck::Zeroable for ArrayVec<T, N> {}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    /// Creates a new empty vector.
+    pub const fn new() -> Self {
+        // SAFETY: An array of MaybeUninit doesn’t require initialisation.
+        let data = unsafe { MaybeUninit::uninit().assume_init() };
+        Self { len: 0, data }
+    }
+
+    /// Returns maximum number of elements the vector can hold.
+    pub const fn capacity(&self) -> usize { N }
+
+    /// Returns whether the vector is full.
+    pub const fn is_full(&self) -> bool { self.len == N }
+
+    /// Returns the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: First len elements are initialised.
+        unsafe { &*(core::ptr::from_ref(&self.data[..self.len]) as *const [T]) }
+    }
+
+    /// Returns the elements as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: First len elements are initialised.
+        unsafe {
+            &mut *(core::ptr::from_mut(&mut self.data[..self.len])
+                as *mut [T])
+        }
+    }
+
+    /// Appends an element to the end of the vector.  Returns the element back
+    /// if the vector is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        self.data[self.len].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last element from the vector and returns it or `None` if
+    /// the vector is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        // SAFETY: The element was initialised and is no longer part of the
+        // vector.
+        Some(unsafe { self.data[self.len].assume_init_read() })
+    }
+
+    /// Inserts an element at position `index` shifting all elements after it
+    /// to the right.  Returns the element back if the vector is full.
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) -> Result<(), T> {
+        assert!(index <= self.len, "insertion index out of bounds");
+        self.push(value)?;
+        self.as_mut_slice()[index..].rotate_right(1);
+        Ok(())
+    }
+
+    /// Removes and returns the element at position `index` shifting all
+    /// elements after it to the left.
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index out of bounds");
+        self.as_mut_slice()[index..].rotate_left(1);
+        self.pop().unwrap()
+    }
+
+    /// Removes and returns the element at position `index` replacing it with
+    /// the last element of the vector.
+    ///
+    /// Panics if `index >= len`.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        let last = self.len.checked_sub(1);
+        assert!(last.is_some_and(|last| index <= last), "index out of bounds");
+        self.as_mut_slice().swap(index, last.unwrap());
+        self.pop().unwrap()
+    }
+
+    /// Shortens the vector to `len` elements dropping the rest.  Does nothing
+    /// if the vector is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    /// Removes all elements from the vector.
+    pub fn clear(&mut self) { self.truncate(0) }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        // SAFETY: First len elements are initialised and are never accessed
+        // again.
+        unsafe { core::ptr::drop_in_place(self.as_mut_slice()) }
+    }
+}
+
+impl<T, const N: usize> core::ops::Deref for ArrayVec<T, N> {
+    type Target = [T];
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T, const N: usize> core::ops::DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] { self.as_mut_slice() }
+}
+
+impl<T: Clone, const N: usize> Clone for ArrayVec<T, N> {
+    fn clone(&self) -> Self {
+        let mut vec = Self::new();
+        for value in self.iter() {
+            // Clone has the same capacity.
+            let _ = vec.push(value.clone());
+        }
+        vec
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(fmtr)
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for ArrayVec<T, N> {
+    fn eq(&self, other: &Self) -> bool { self.as_slice() == other.as_slice() }
+}
+
+impl<T: Eq, const N: usize> Eq for ArrayVec<T, N> {}
+
+impl<'a, T, const N: usize> IntoIterator for &'a ArrayVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter { self.iter() }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut ArrayVec<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = core::slice::IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter { self.iter_mut() }
+}
+
+
+/// String with fixed capacity of `N` bytes storing its contents inline.
+///
+/// [`bytemuck::Zeroable`] with zeroed object being an empty string.
+/// Dereferences to `str`.
+#[derive(Clone, Copy)]
+pub struct ArrayString<const N: usize> {
+    len: usize,
+    bytes: [u8; N],
+}
+
+// SAFETY: Zero length means an empty string.
+unsafe impl<const N: usize> bytemuck::Zeroable for ArrayString<N> {}
+
+impl<const N: usize> ArrayString<N> {
+    /// Creates a new empty string.
+    pub const fn new() -> Self { Self { len: 0, bytes: [0; N] } }
+
+    /// Returns maximum length of the string in bytes.
+    pub const fn capacity(&self) -> usize { N }
+
+    /// Returns the string as a `str`.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: Only complete strings are ever appended to the buffer.
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    /// Appends a string to the end of this one.  Fails without modifying the
+    /// string if there’s not enough space.
+    pub fn push_str(&mut self, s: &str) -> Result<(), CapacityError> {
+        let end = self.len + s.len();
+        let dst = self.bytes.get_mut(self.len..end).ok_or(CapacityError)?;
+        dst.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+
+    /// Appends a character to the end of the string.  Fails without modifying
+    /// the string if there’s not enough space.
+    pub fn push(&mut self, ch: char) -> Result<(), CapacityError> {
+        self.push_str(ch.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Removes the last character from the string and returns it or `None` if
+    /// the string is empty.
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.as_str().chars().next_back()?;
+        self.len -= ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Shortens the string to `len` bytes.  Does nothing if the string is
+    /// already shorter.
+    ///
+    /// Panics if `len` doesn’t lie on a character boundary.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            assert!(self.is_char_boundary(len), "not a char boundary");
+            self.len = len;
+        }
+    }
+
+    /// Empties the string.
+    pub fn clear(&mut self) { self.len = 0 }
+}
+
+impl<const N: usize> Default for ArrayString<N> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<const N: usize> core::ops::Deref for ArrayString<N> {
+    type Target = str;
+    fn deref(&self) -> &str { self.as_str() }
+}
+
+impl<const N: usize> fmt::Write for ArrayString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Display for ArrayString<N> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(fmtr)
+    }
+}
+
+impl<const N: usize> fmt::Debug for ArrayString<N> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(fmtr)
+    }
+}
+
+impl<const N: usize> PartialEq for ArrayString<N> {
+    fn eq(&self, other: &Self) -> bool { self.as_str() == other.as_str() }
+}
+
+impl<const N: usize> Eq for ArrayString<N> {}
+
+impl<const N: usize> PartialEq<str> for ArrayString<N> {
+    fn eq(&self, other: &str) -> bool { self.as_str() == other }
+}
+
+impl<const N: usize> PartialEq<&str> for ArrayString<N> {
+    fn eq(&self, other: &&str) -> bool { self.as_str() == *other }
+}
+
+
+/// Map with fixed capacity of `N` entries storing them inline.
+///
+/// Entries are kept in insertion order (until one is removed) and looked up
+/// with linear search which is efficient for small maps.
+/// [`bytemuck::Zeroable`] for any `K` and `V` with zeroed object being an
+/// empty map.
+pub struct ArrayMap<K, V, const N: usize> {
+    entries: ArrayVec<(K, V), N>,
+}
+
+// SAFETY: ArrayVec is Zeroable for any element type.
+unsafe impl<K, V, const N: usize> bytemuck::Zeroable for ArrayMap<K, V, N> {}
+
+impl<K, V, const N: usize> ArrayMap<K, V, N> {
+    /// Creates a new empty map.
+    pub const fn new() -> Self { Self { entries: ArrayVec::new() } }
+
+    /// Returns number of entries in the map.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Returns whether the map is empty.
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Returns maximum number of entries the map can hold.
+    pub const fn capacity(&self) -> usize { N }
+
+    /// Returns iterator over the entries of the map.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    /// Returns iterator over the entries of the map with mutable references
+    /// to the values.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.entries.iter_mut().map(|(key, value)| (&*key, value))
+    }
+
+    /// Returns iterator over the keys of the map.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    /// Returns iterator over the values of the map.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) { self.entries.clear() }
+}
+
+impl<K: PartialEq, V, const N: usize> ArrayMap<K, V, N> {
+    /// Returns position of the entry with given key.
+    fn position(&self, key: &K) -> Option<usize> {
+        self.entries.iter().position(|(k, _)| k == key)
+    }
+
+    /// Returns whether the map contains given key.
+    pub fn contains_key(&self, key: &K) -> bool { self.position(key).is_some() }
+
+    /// Returns reference to the value corresponding to given key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.position(key).map(|idx| &self.entries[idx].1)
+    }
+
+    /// Returns mutable reference to the value corresponding to given key.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.position(key).map(|idx| &mut self.entries[idx].1)
+    }
+
+    /// Inserts an entry into the map.
+    ///
+    /// If the map already had an entry with given key, replaces its value and
+    /// returns the old value.  If the map is full, returns the entry back.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        match self.position(&key) {
+            Some(idx) => {
+                Ok(Some(core::mem::replace(&mut self.entries[idx].1, value)))
+            }
+            None => self.entries.push((key, value)).map(|()| None),
+        }
+    }
+
+    /// Removes entry with given key from the map returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.position(key)?;
+        Some(self.entries.swap_remove(idx).1)
+    }
+}
+
+impl<K, V, const N: usize> Default for ArrayMap<K, V, N> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<K: Clone, V: Clone, const N: usize> Clone for ArrayMap<K, V, N> {
+    fn clone(&self) -> Self { Self { entries: self.entries.clone() } }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, const N: usize> fmt::Debug
+    for ArrayMap<K, V, N>
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,105 @@
+use alloc::rc::Rc;
+use core::fmt::Write;
+
+use super::*;
+use crate::cell::RefCell;
+
+#[test]
+fn test_array_vec() {
+    let mut vec: ArrayVec<u32, 4> = bytemuck::Zeroable::zeroed();
+    assert!(vec.is_empty());
+    assert_eq!(4, vec.capacity());
+
+    for value in 1..=4 {
+        vec.push(value).unwrap();
+    }
+    assert!(vec.is_full());
+    assert_eq!(Err(5), vec.push(5));
+    assert_eq!(Err(5), vec.insert(0, 5));
+    assert_eq!(&[1, 2, 3, 4], vec.as_slice());
+
+    assert_eq!(Some(4), vec.pop());
+    vec.insert(0, 0).unwrap();
+    assert_eq!(&[0, 1, 2, 3], vec.as_slice());
+    assert_eq!(1, vec.remove(1));
+    assert_eq!(0, vec.swap_remove(0));
+    assert_eq!(&[3, 2], vec.as_slice());
+    vec[1] = 42;
+    assert_eq!("[3, 42]", std::format!("{vec:?}"));
+
+    vec.clear();
+    assert_eq!(None, vec.pop());
+}
+
+#[test]
+fn test_array_vec_drop() {
+    let value = Rc::new(());
+    let mut vec = ArrayVec::<Rc<()>, 4>::new();
+    vec.push(value.clone()).unwrap();
+    vec.push(value.clone()).unwrap();
+    vec.push(value.clone()).unwrap();
+    assert_eq!(4, Rc::strong_count(&value));
+    vec.truncate(2);
+    assert_eq!(3, Rc::strong_count(&value));
+    core::mem::drop(vec);
+    assert_eq!(1, Rc::strong_count(&value));
+}
+
+#[test]
+fn test_array_string() {
+    let mut s: ArrayString<8> = bytemuck::Zeroable::zeroed();
+    assert_eq!("", s.as_str());
+    s.push_str("zaż").unwrap();
+    s.push('ó').unwrap();
+    assert_eq!("zażó", s.as_str());
+    // Strings which don’t fit are not appended.
+    assert_eq!(Err(CapacityError), s.push_str("łć"));
+    assert_eq!("zażó", s.as_str());
+    s.push('ł').unwrap();
+    assert_eq!(Err(CapacityError), s.push('x'));
+
+    assert_eq!(Some('ł'), s.pop());
+    s.truncate(2);
+    assert_eq!("za", s.as_str());
+    write!(s, "-{}", 42).unwrap();
+    assert_eq!("za-42", s.as_str());
+    assert!(write!(s, "{}", 1234).is_err());
+}
+
+#[test]
+fn test_array_map() {
+    let mut map: ArrayMap<u8, &str, 2> = bytemuck::Zeroable::zeroed();
+    assert_eq!(None, map.get(&1));
+    assert_eq!(Ok(None), map.insert(1, "foo"));
+    assert_eq!(Ok(None), map.insert(2, "bar"));
+    assert_eq!(Err((3, "baz")), map.insert(3, "baz"));
+    assert_eq!(Ok(Some("bar")), map.insert(2, "qux"));
+    assert_eq!(Some(&"foo"), map.get(&1));
+    assert_eq!(Some(&"qux"), map.get(&2));
+    assert_eq!("{1: \"foo\", 2: \"qux\"}", std::format!("{map:?}"));
+
+    *map.get_mut(&1).unwrap() = "baz";
+    assert_eq!(Some("baz"), map.remove(&1));
+    assert_eq!(None, map.remove(&1));
+    assert!(!map.contains_key(&1));
+    assert_eq!(1, map.len());
+}
+
+#[test]
+fn test_in_global() {
+    #[derive(bytemuck::Zeroable)]
+    struct Global {
+        ids: RefCell<ArrayVec<u64, 8>>,
+        name: RefCell<ArrayString<16>>,
+        balances: RefCell<ArrayMap<u64, u64, 4>>,
+    }
+
+    let allocator = crate::BumpAllocator::<Global>::new(1024);
+    let global = allocator.global();
+    global.ids.borrow_mut().push(42).unwrap();
+    global.name.borrow_mut().push_str("foo").unwrap();
+    global.balances.borrow_mut().insert(42, 100).unwrap();
+    assert_eq!(&[42], global.ids.borrow().as_slice());
+    assert_eq!("foo", global.name.borrow().as_str());
+    assert_eq!(Some(&100), global.balances.borrow().get(&42));
+}
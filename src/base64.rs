@@ -0,0 +1,61 @@
+//! Standard base64 encoding with padding as used for program data and return
+//! data in transaction logs.
+
+use alloc::vec::Vec;
+
+#[cfg(test)]
+mod tests;
+
+/// Alphabet of standard base64 encoding.
+const ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Formats bytes in standard base64 with padding.
+#[cfg(all(
+    feature = "trace",
+    any(test, feature = "testing", fixed_heap),
+    not(target_os = "solana")
+))]
+pub(crate) struct Base64<'a>(pub &'a [u8]);
+
+#[cfg(all(
+    feature = "trace",
+    any(test, feature = "testing", fixed_heap),
+    not(target_os = "solana")
+))]
+impl core::fmt::Display for Base64<'_> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter) -> core::fmt::Result {
+        use core::fmt::Write;
+        for chunk in self.0.chunks(3) {
+            let mut bytes = [0; 3];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let acc = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for idx in 0..4 {
+                if idx <= chunk.len() {
+                    let value = (acc >> (18 - 6 * idx)) & 63;
+                    fmtr.write_char(char::from(ALPHABET[value as usize]))?;
+                } else {
+                    fmtr.write_char('=')?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes standard base64 with padding.
+///
+/// Returns `None` if `data` holds characters outside of the base64 alphabet.
+pub(crate) fn decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0u32;
+    for (idx, &ch) in data.iter().enumerate() {
+        let value = ALPHABET.iter().position(|&c| c == ch)?;
+        acc = (acc << 6) | value as u32;
+        if idx % 4 != 0 {
+            out.push((acc >> (6 - 2 * (idx % 4))) as u8);
+        }
+    }
+    Some(out)
+}
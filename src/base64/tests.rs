@@ -0,0 +1,29 @@
+use super::*;
+
+/// Pairs of data and its base64 encoding.
+const CASES: [(&[u8], &str); 6] = [
+    (b"", ""),
+    (b"f", "Zg=="),
+    (b"fo", "Zm8="),
+    (b"foo", "Zm9v"),
+    (b"foobar", "Zm9vYmFy"),
+    (&[0xFF, 0xFE, 0x00, 0x80], "//4AgA=="),
+];
+
+#[test]
+fn test_decode() {
+    for (data, encoded) in CASES {
+        assert_eq!(Some(data.to_vec()), decode(encoded));
+    }
+    assert_eq!(None, decode("Zm9v!"));
+}
+
+#[test]
+#[cfg(feature = "trace")]
+fn test_encode() {
+    use alloc::string::ToString;
+
+    for (data, encoded) in CASES {
+        assert_eq!(encoded, Base64(data).to_string());
+    }
+}
@@ -0,0 +1,78 @@
+//! Recommends `RequestHeapFrame` size based on transaction logs.
+//!
+//! Reads transaction logs (e.g. output of `solana confirm -v` or of
+//! a transaction simulation) from files given on the command line or from
+//! standard input.  Extracts heap usage lines emitted by
+//! `solana_allocator::report` and prints per-instruction breakdown followed by
+//! the smallest heap frame size which fits all the instructions.
+//!
+//! Exits with non-zero status if no heap usage lines were found or if the
+//! instructions need more heap than can be requested.
+
+use std::io::Read;
+
+use solana_allocator::report::{from_logs, heap_frame, ProgramReport, Report};
+use solana_allocator::sysvar::MAX_HEAP_FRAME;
+
+fn read(paths: &[String]) -> std::io::Result<Vec<ProgramReport>> {
+    let mut logs = String::new();
+    if paths.is_empty() {
+        std::io::stdin().read_to_string(&mut logs)?;
+    }
+    for path in paths {
+        logs.push_str(&std::fs::read_to_string(path)?);
+        logs.push('\n');
+    }
+    Ok(from_logs(logs.lines()))
+}
+
+fn main() -> std::process::ExitCode {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("usage: heap-frame [<log-file>...]");
+        return std::process::ExitCode::SUCCESS;
+    }
+    let entries = match read(&paths) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("heap-frame: {err}");
+            return std::process::ExitCode::FAILURE;
+        }
+    };
+    if entries.is_empty() {
+        eprintln!("heap-frame: no heap usage reports found");
+        return std::process::ExitCode::FAILURE;
+    }
+
+    println!(
+        "{:>3} {:<44} {:>8} {:>8} {:>8}",
+        "#", "program", "before", "after", "peak"
+    );
+    for (idx, entry) in entries.iter().enumerate() {
+        let Report { before, after, peak } = entry.report;
+        let program = entry.program.as_deref().unwrap_or("?");
+        println!(
+            "{:>3} {program:<44} {before:>8} {after:>8} {peak:>8}",
+            idx + 1
+        );
+    }
+
+    let peak = entries.iter().map(|entry| entry.report.peak).max().unwrap();
+    match heap_frame(peak) {
+        Some(size) => {
+            println!("RequestHeapFrame: {size} ({} KiB)", size / 1024);
+            std::process::ExitCode::SUCCESS
+        }
+        None => {
+            println!(
+                "RequestHeapFrame: {MAX_HEAP_FRAME} ({} KiB)",
+                MAX_HEAP_FRAME / 1024
+            );
+            eprintln!(
+                "heap-frame: peak usage of {peak} bytes exceeds maximum heap \
+                 frame size"
+            );
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
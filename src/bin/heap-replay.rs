@@ -0,0 +1,98 @@
+//! Replays allocation traces with allocators provided by the crate.
+//!
+//! Reads a trace recorded with `BumpAllocator::start_trace` from a file given
+//! on the command line or from standard input.  The input can be transaction
+//! logs with the trace flushed to them or the raw trace (e.g. returned by
+//! `BumpAllocator::stop_trace` and saved by a test).  Replays the trace with
+//! each allocation strategy and prints its peak heap usage and fragmentation
+//! together with the smallest heap frame which fits it.
+//!
+//! The heap is as large as the largest heap frame which can be requested
+//! unless `--heap-size` option is given.  Exits with non-zero status if the
+//! trace is malformed or empty.
+
+use solana_allocator::report::heap_frame;
+use solana_allocator::sysvar::MAX_HEAP_FRAME;
+use solana_allocator::trace::{self, Strategy};
+
+const USAGE: &str = "usage: heap-replay [--heap-size <bytes>] [<file>]";
+
+fn main() -> std::process::ExitCode {
+    let mut heap_size = MAX_HEAP_FRAME as usize;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return std::process::ExitCode::SUCCESS;
+            }
+            "--heap-size" => {
+                match args.next().and_then(|size| size.parse().ok()) {
+                    Some(size) if size > 0 => heap_size = size,
+                    _ => {
+                        eprintln!("heap-replay: invalid heap size\n{USAGE}");
+                        return std::process::ExitCode::FAILURE;
+                    }
+                }
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return std::process::ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let trace = match trace::read(path.as_deref()) {
+        Ok(trace) if !trace.is_empty() => trace,
+        Ok(_) => {
+            eprintln!("heap-replay: no trace found");
+            return std::process::ExitCode::FAILURE;
+        }
+        Err(err) => {
+            eprintln!("heap-replay: {err}");
+            return std::process::ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "{:<10} {:>7} {:>8} {:>8} {:>8} {:>6} {:>7} {:>10}",
+        "strategy",
+        "events",
+        "peak",
+        "end",
+        "frag",
+        "failed",
+        "skipped",
+        "heap-frame"
+    );
+    let mut truncated = false;
+    for (name, strategy) in
+        [("bump", Strategy::Bump), ("free-list", Strategy::FreeList)]
+    {
+        let summary = match trace::replay(&trace, strategy, heap_size) {
+            Ok(summary) => summary,
+            Err(err) => {
+                eprintln!("heap-replay: {err}");
+                return std::process::ExitCode::FAILURE;
+            }
+        };
+        truncated |= summary.truncated;
+        let frame = heap_frame(summary.peak)
+            .map_or_else(|| "too large".to_string(), |size| size.to_string());
+        println!(
+            "{name:<10} {:>7} {:>8} {:>8} {:>8} {:>6} {:>7} {frame:>10}",
+            summary.events,
+            summary.peak,
+            summary.end,
+            summary.fragmentation,
+            summary.failed,
+            summary.skipped,
+        );
+    }
+    if truncated {
+        eprintln!("heap-replay: trace truncated; results are incomplete");
+    }
+    std::process::ExitCode::SUCCESS
+}
@@ -0,0 +1,107 @@
+//! Renders layout of the heap over the course of an allocation trace.
+//!
+//! Reads a trace recorded with `BumpAllocator::start_trace` from a file given
+//! on the command line or from standard input.  Like with `heap-replay`, the
+//! input can be transaction logs with the trace flushed to them or the raw
+//! trace (e.g. one recorded in a unit test with the test-mode allocator and
+//! saved to a file).  Replays the trace and prints layout of the heap after
+//! each event as a row of text or, with `--svg` option, as an SVG image.
+//!
+//! Each row shows allocator’s header, live blocks, memory of freed blocks
+//! which hasn’t been reclaimed and alignment padding.  The 32 KiB boundary of
+//! the default heap frame and the event at which heap usage peaked are
+//! marked.
+
+use solana_allocator::sysvar::{DEFAULT_HEAP_FRAME, MAX_HEAP_FRAME};
+use solana_allocator::trace::{self, Strategy};
+
+const USAGE: &str = "usage: heap-timeline [--svg] [--width <columns>] \
+                     [--strategy bump|free-list] [--heap-size <bytes>] \
+                     [<file>]";
+
+/// Command line options.
+struct Options {
+    svg: bool,
+    width: usize,
+    strategy: Strategy,
+    heap_size: usize,
+    path: Option<String>,
+}
+
+impl Options {
+    /// Parses command line arguments.  Returns `None` if they’re invalid.
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut opts = Self {
+            svg: false,
+            width: 64,
+            strategy: Strategy::Bump,
+            heap_size: MAX_HEAP_FRAME as usize,
+            path: None,
+        };
+        let positive = |arg: Option<String>| {
+            arg?.parse().ok().filter(|&value: &usize| value > 0)
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--svg" => opts.svg = true,
+                "--width" => opts.width = positive(args.next())?,
+                "--heap-size" => opts.heap_size = positive(args.next())?,
+                "--strategy" => {
+                    opts.strategy = match args.next()?.as_str() {
+                        "bump" => Strategy::Bump,
+                        "free-list" => Strategy::FreeList,
+                        _ => return None,
+                    }
+                }
+                _ if opts.path.is_none() && !arg.starts_with('-') => {
+                    opts.path = Some(arg)
+                }
+                _ => return None,
+            }
+        }
+        Some(opts)
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{USAGE}");
+        return std::process::ExitCode::SUCCESS;
+    }
+    let Some(opts) = Options::parse(args.into_iter()) else {
+        eprintln!("{USAGE}");
+        return std::process::ExitCode::FAILURE;
+    };
+
+    let timeline = match trace::read(opts.path.as_deref())
+        .map(|trace| trace::timeline(&trace, opts.strategy, opts.heap_size))
+    {
+        Ok(Ok(timeline)) if !timeline.frames.is_empty() => timeline,
+        Ok(Ok(_)) => {
+            eprintln!("heap-timeline: no trace found");
+            return std::process::ExitCode::FAILURE;
+        }
+        Ok(Err(err)) => {
+            eprintln!("heap-timeline: {err}");
+            return std::process::ExitCode::FAILURE;
+        }
+        Err(err) => {
+            eprintln!("heap-timeline: {err}");
+            return std::process::ExitCode::FAILURE;
+        }
+    };
+    if timeline.summary.truncated {
+        eprintln!("heap-timeline: trace truncated; timeline is incomplete");
+    }
+
+    // Always show the 32 KiB boundary even if the heap usage is smaller.
+    let peak = timeline.peak_frame().map_or(0, |idx| timeline.frames[idx].end);
+    let scale = peak.max(DEFAULT_HEAP_FRAME as usize);
+    if opts.svg {
+        print!("{}", timeline.render_svg(scale));
+    } else {
+        print!("{}", timeline.render_text(scale, opts.width));
+    }
+    std::process::ExitCode::SUCCESS
+}
@@ -0,0 +1,414 @@
+//! Cell types which can be used as fields of the global state.
+//!
+//! Global state declared with [`custom_global`](`crate::custom_global`) must be
+//! [`bytemuck::Zeroable`] since it lives in zero-initialised heap memory.  This
+//! module provides wrappers which are zeroable themselves and allow storing
+//! arbitrary types in the global state.
+//!
+//! [`RefCell`] and [`OnceCell`] are zeroable equivalents of types with the
+//! same names in [`core::cell`].  [`SolanaLocal`] is a wrapper which is
+//! [`Sync`] when building for Solana.
+
+use core::cell::{Cell, UnsafeCell};
+use core::mem::MaybeUninit;
+
+#[cfg(test)]
+mod tests;
+
+/// Lazily initialised value stored on the heap.
+///
+/// This is a [`bytemuck::Zeroable`] type which can be used as a field of the
+/// global state to hold types which aren’t zeroable, e.g. `Vec` or
+/// `BTreeMap`.  The value is built on first access to
+/// [`get_or_init`](`Self::get_or_init`) and allocated with the global
+/// allocator.  The value is never dropped.
+///
+/// # Example
+///
+/// ```ignore
+/// solana_allocator::custom_global!(struct GlobalData {
+///     config: GlobalLazy<Vec<Pubkey>>,
+/// });
+///
+/// fn config(account: &AccountInfo) -> &'static [Pubkey] {
+///     global().config.get_or_init(|| parse_config(account))
+/// }
+/// ```
+///
+/// Note that if the value is initialised after a
+/// [`BumpAllocator::checkpoint`](`crate::BumpAllocator::checkpoint`), the
+/// corresponding rollback frees it.  It’s caller’s responsibility to make sure
+/// the global isn’t accessed after such a rollback.
+pub struct GlobalLazy<T> {
+    ptr: Cell<*mut T>,
+}
+
+// SAFETY: The only field is a pointer which is null when zeroed.
+unsafe impl<T> bytemuck::Zeroable for GlobalLazy<T> {}
+
+impl<T> GlobalLazy<T> {
+    /// Creates a new uninitialised object.
+    pub const fn new() -> Self {
+        Self { ptr: Cell::new(core::ptr::null_mut()) }
+    }
+
+    /// Returns reference to the value or `None` if it hasn’t been initialised
+    /// yet.
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: Non-null pointer points at an initialised value which is
+        // never modified nor freed.
+        unsafe { self.ptr.get().as_ref() }
+    }
+
+    /// Returns reference to the value initialising it with `init` if it
+    /// hasn’t been initialised yet.
+    ///
+    /// Panics if `init` initialises the object.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = init();
+        assert!(self.ptr.get().is_null(), "GlobalLazy initialised reentrantly");
+        let ptr = alloc::boxed::Box::into_raw(alloc::boxed::Box::new(value));
+        self.ptr.set(ptr);
+        // SAFETY: We’ve just set the pointer to a valid value.
+        unsafe { &*ptr }
+    }
+}
+
+impl<T> Default for GlobalLazy<T> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Debug for GlobalLazy<T> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.get() {
+            Some(value) => fmtr.debug_tuple("GlobalLazy").field(value).finish(),
+            None => fmtr.write_str("GlobalLazy(<uninit>)"),
+        }
+    }
+}
+
+
+/// Mutable memory location with dynamically checked borrow rules.
+///
+/// This is like [`core::cell::RefCell`] except that it’s
+/// [`bytemuck::Zeroable`] if `T` is.  Zeroed `RefCell` is not borrowed and
+/// holds zeroed `T`.
+pub struct RefCell<T> {
+    /// Number of shared borrows or [`WRITING`] if the value is mutably
+    /// borrowed.
+    borrow: Cell<usize>,
+    value: UnsafeCell<T>,
+}
+
+/// [`RefCell::borrow`] state of a mutably borrowed cell.
+const WRITING: usize = usize::MAX;
+
+// SAFETY: Zero borrow count means the cell isn’t borrowed and T is Zeroable.
+unsafe impl<T: bytemuck::Zeroable> bytemuck::Zeroable for RefCell<T> {}
+
+/// Shared borrow of a [`RefCell`] value.
+pub struct Ref<'a, T> {
+    cell: &'a RefCell<T>,
+}
+
+/// Mutable borrow of a [`RefCell`] value.
+pub struct RefMut<'a, T> {
+    cell: &'a RefCell<T>,
+}
+
+impl<T> RefCell<T> {
+    /// Creates a new cell holding given value.
+    pub const fn new(value: T) -> Self {
+        Self { borrow: Cell::new(0), value: UnsafeCell::new(value) }
+    }
+
+    /// Immutably borrows the value or returns `None` if it’s mutably
+    /// borrowed.
+    pub fn try_borrow(&self) -> Option<Ref<'_, T>> {
+        let count = self.borrow.get();
+        (count < WRITING - 1).then(|| {
+            self.borrow.set(count + 1);
+            Ref { cell: self }
+        })
+    }
+
+    /// Mutably borrows the value or returns `None` if it’s borrowed.
+    pub fn try_borrow_mut(&self) -> Option<RefMut<'_, T>> {
+        (self.borrow.get() == 0).then(|| {
+            self.borrow.set(WRITING);
+            RefMut { cell: self }
+        })
+    }
+
+    /// Immutably borrows the value.
+    ///
+    /// Panics if the value is mutably borrowed.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("RefCell already mutably borrowed")
+    }
+
+    /// Mutably borrows the value.
+    ///
+    /// Panics if the value is borrowed.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut().expect("RefCell already borrowed")
+    }
+
+    /// Replaces the value returning the old one.
+    ///
+    /// Panics if the value is borrowed.
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        core::mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Returns mutable reference to the value.
+    pub fn get_mut(&mut self) -> &mut T { self.value.get_mut() }
+
+    /// Consumes the cell returning the value.
+    pub fn into_inner(self) -> T { self.value.into_inner() }
+}
+
+impl<T: Default> Default for RefCell<T> {
+    fn default() -> Self { Self::new(T::default()) }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Debug for RefCell<T> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.try_borrow() {
+            Some(value) => fmtr.debug_tuple("RefCell").field(&*value).finish(),
+            None => fmtr.write_str("RefCell(<borrowed>)"),
+        }
+    }
+}
+
+impl<T> core::ops::Deref for Ref<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: The cell is immutably borrowed.
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for Ref<'_, T> {
+    fn drop(&mut self) { self.cell.borrow.set(self.cell.borrow.get() - 1) }
+}
+
+impl<T> core::ops::Deref for RefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: The cell is mutably borrowed by us.
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> core::ops::DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The cell is mutably borrowed by us.
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for RefMut<'_, T> {
+    fn drop(&mut self) { self.cell.borrow.set(0) }
+}
+
+
+/// Cell which can be written to only once.
+///
+/// This is like [`core::cell::OnceCell`] except that it’s
+/// [`bytemuck::Zeroable`] for any `T`.  Zeroed `OnceCell` is empty.  Unlike
+/// [`GlobalLazy`], the value is stored inline.
+pub struct OnceCell<T> {
+    ready: Cell<bool>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: Zeroed `ready` flag means the value is uninitialised.
+unsafe impl<T> bytemuck::Zeroable for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    pub const fn new() -> Self {
+        Self {
+            ready: Cell::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns reference to the value or `None` if the cell is empty.
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: Once set, the value is never modified until the cell is
+        // dropped or mutably borrowed.
+        self.ready
+            .get()
+            .then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Sets the value of the cell.  Returns the value back if the cell is
+    /// already full.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.ready.get() {
+            return Err(value);
+        }
+        // SAFETY: The cell is empty so there are no references to the value.
+        unsafe { (*self.value.get()).write(value) };
+        self.ready.set(true);
+        Ok(())
+    }
+
+    /// Returns reference to the value initialising it with `init` if the
+    /// cell is empty.
+    ///
+    /// Panics if `init` initialises the cell.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = init();
+        if self.set(value).is_err() {
+            panic!("OnceCell initialised reentrantly");
+        }
+        self.get().unwrap()
+    }
+
+    /// Returns mutable reference to the value or `None` if the cell is
+    /// empty.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        // SAFETY: The value is initialised if ready flag is set.
+        self.ready
+            .get()
+            .then(|| unsafe { self.value.get_mut().assume_init_mut() })
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Debug for OnceCell<T> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.get() {
+            Some(value) => fmtr.debug_tuple("OnceCell").field(value).finish(),
+            None => fmtr.write_str("OnceCell(<uninit>)"),
+        }
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if self.ready.get() {
+            // SAFETY: The value is initialised.
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+
+/// Wrapper which is [`Sync`] when building for Solana.
+///
+/// Solana programs are single-threaded so sharing data between threads is not
+/// a concern.  This wrapper lets types with interior mutability (such as
+/// [`Cell`](`core::cell::Cell`) or [`RefCell`]) be used in statics and global
+/// state without resorting to `unsafe impl Sync`.  When not building for
+/// Solana, the wrapper is `Sync` only if `T` is.
+///
+/// [`custom_global`](`crate::custom_global`) macro wraps the global state in
+/// this type.
+#[derive(Default, Debug)]
+#[repr(transparent)]
+pub struct SolanaLocal<T>(T);
+
+// SAFETY: Solana is single-threaded.
+#[cfg(target_os = "solana")]
+unsafe impl<T> Sync for SolanaLocal<T> {}
+
+// SAFETY: SolanaLocal is a transparent wrapper.
+unsafe impl<T: bytemuck::Zeroable> bytemuck::Zeroable for SolanaLocal<T> {}
+
+impl<T> SolanaLocal<T> {
+    /// Wraps given value.
+    pub const fn new(value: T) -> Self { Self(value) }
+
+    /// Consumes the wrapper returning the value.
+    pub fn into_inner(self) -> T { self.0 }
+}
+
+impl<T> core::ops::Deref for SolanaLocal<T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.0 }
+}
+
+impl<T> core::ops::DerefMut for SolanaLocal<T> {
+    fn deref_mut(&mut self) -> &mut T { &mut self.0 }
+}
+
+
+/// Global state together with its borrow state.
+///
+/// This is an implementation detail used by the allocators and
+/// [`custom_global`](`crate::custom_global`) macro.  Zeroed object holds
+/// zeroed `G` which isn’t borrowed.  The value is the last field so that with
+/// `debug-heap` feature out-of-bounds writes past it hit canary in the
+/// allocator’s header.
+#[doc(hidden)]
+#[repr(C)]
+pub struct GlobalCell<G> {
+    /// One of [`UNBORROWED`], [`SHARED`] or [`EXCLUSIVE`].
+    borrow: Cell<u8>,
+    value: UnsafeCell<G>,
+}
+
+/// [`GlobalCell::borrow`] state of global state which hasn’t been accessed.
+const UNBORROWED: u8 = 0;
+/// [`GlobalCell::borrow`] state of global state to which a shared reference
+/// has been handed out.  Since the reference is `'static`, this state is never
+/// left.
+const SHARED: u8 = 1;
+/// [`GlobalCell::borrow`] state of global state which is mutably borrowed.
+const EXCLUSIVE: u8 = 2;
+
+// SAFETY: Zeroed borrow state is UNBORROWED and G is Zeroable.
+unsafe impl<G: bytemuck::Zeroable> bytemuck::Zeroable for GlobalCell<G> {}
+
+impl<G> GlobalCell<G> {
+    /// Returns shared reference to the value.
+    ///
+    /// Panics if the value is mutably borrowed.
+    #[track_caller]
+    pub fn get(&self) -> &G {
+        match self.borrow.get() {
+            SHARED => (),
+            UNBORROWED => self.borrow.set(SHARED),
+            _ => panic!("global state already mutably borrowed"),
+        }
+        // SAFETY: The value is not mutably borrowed and won’t be from now on.
+        unsafe { &*self.value.get() }
+    }
+
+    /// Calls `f` with mutable reference to the value.
+    ///
+    /// Returns `None` if the value is borrowed.
+    pub fn try_with_mut<R>(&self, f: impl FnOnce(&mut G) -> R) -> Option<R> {
+        /// Releases the mutable borrow even if `f` panics.
+        struct Guard<'a>(&'a Cell<u8>);
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) { self.0.set(UNBORROWED) }
+        }
+
+        if self.borrow.get() != UNBORROWED {
+            return None;
+        }
+        self.borrow.set(EXCLUSIVE);
+        let _guard = Guard(&self.borrow);
+        // SAFETY: The value is not borrowed and EXCLUSIVE state prevents any
+        // other borrows until the guard is dropped.
+        Some(f(unsafe { &mut *self.value.get() }))
+    }
+}
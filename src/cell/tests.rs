@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+
+use super::*;
+
+#[test]
+fn test_global_lazy() {
+    let lazy = GlobalLazy::<Vec<u32>>::new();
+    assert_eq!(None, lazy.get());
+    assert_eq!("GlobalLazy(<uninit>)", std::format!("{lazy:?}"));
+
+    assert_eq!(&[1, 2, 3], lazy.get_or_init(|| [1, 2, 3].into()).as_slice());
+    assert_eq!(Some(&[1, 2, 3][..]), lazy.get().map(Vec::as_slice));
+    // Initialiser is not called again.
+    assert_eq!(&[1, 2, 3], lazy.get_or_init(|| unreachable!()).as_slice());
+    assert_eq!("GlobalLazy([1, 2, 3])", std::format!("{lazy:?}"));
+}
+
+#[test]
+fn test_global_lazy_in_global() {
+    #[derive(bytemuck::Zeroable)]
+    struct Global {
+        counter: Cell<u32>,
+        names: GlobalLazy<Vec<&'static str>>,
+    }
+
+    let allocator = crate::BumpAllocator::<Global>::new(1024);
+    let global = allocator.global();
+    assert_eq!(0, global.counter.get());
+    assert_eq!(None, global.names.get());
+    global.names.get_or_init(|| ["foo", "bar"].into());
+    assert_eq!(
+        Some(&["foo", "bar"][..]),
+        allocator.global().names.get().map(Vec::as_slice)
+    );
+}
+
+#[test]
+#[should_panic = "initialised reentrantly"]
+fn test_global_lazy_reentrant() {
+    let lazy = GlobalLazy::<u32>::new();
+    lazy.get_or_init(|| *lazy.get_or_init(|| 1) + 1);
+}
+
+#[test]
+fn test_ref_cell() {
+    let cell: RefCell<(u32, u64)> = bytemuck::Zeroable::zeroed();
+    assert_eq!((0, 0), *cell.borrow());
+
+    {
+        let first = cell.borrow();
+        let second = cell.try_borrow().unwrap();
+        assert_eq!(first.0, second.0);
+        assert!(cell.try_borrow_mut().is_none());
+    }
+
+    {
+        let mut value = cell.borrow_mut();
+        value.1 = 42;
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+        assert_eq!("RefCell(<borrowed>)", std::format!("{cell:?}"));
+    }
+
+    assert_eq!((0, 42), cell.replace((1, 2)));
+    assert_eq!("RefCell((1, 2))", std::format!("{cell:?}"));
+}
+
+#[test]
+#[should_panic = "already borrowed"]
+fn test_ref_cell_aliasing() {
+    let cell = RefCell::new(0u32);
+    let _value = cell.borrow();
+    *cell.borrow_mut() += 1;
+}
+
+#[test]
+fn test_once_cell() {
+    let cell: OnceCell<std::string::String> = bytemuck::Zeroable::zeroed();
+    assert_eq!(None, cell.get());
+    assert_eq!("OnceCell(<uninit>)", std::format!("{cell:?}"));
+    assert_eq!("foo", cell.get_or_init(|| "foo".into()));
+    assert_eq!(Err("bar".into()), cell.set("bar".into()));
+    assert_eq!("foo", cell.get_or_init(|| unreachable!()));
+    assert_eq!("OnceCell(\"foo\")", std::format!("{cell:?}"));
+}
+
+#[test]
+#[should_panic = "initialised reentrantly"]
+fn test_once_cell_reentrant() {
+    let cell = OnceCell::<u32>::new();
+    cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+}
+
+#[test]
+fn test_solana_local_in_global() {
+    #[derive(bytemuck::Zeroable)]
+    struct Global {
+        counter: RefCell<u32>,
+        name: OnceCell<&'static str>,
+    }
+
+    let allocator = crate::BumpAllocator::<SolanaLocal<Global>>::new(1024);
+    let global: &Global = allocator.global();
+    *global.counter.borrow_mut() += 1;
+    global.name.set("foo").unwrap();
+    assert_eq!(1, *allocator.global().counter.borrow());
+    assert_eq!(Some(&"foo"), allocator.global().name.get());
+}
@@ -0,0 +1,483 @@
+//! General-purpose allocator with a coalescing free list.
+//!
+//! Bump allocator can reclaim memory only if objects are freed in reverse
+//! order of allocation.  This is usually good enough for Solana programs but
+//! when long-lived and short-lived objects are mixed, memory freed from the
+//! middle of the heap is never reused.  This module defines an allocator which
+//! keeps track of freed regions and reuses them for new allocations.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+
+use crate::cell::GlobalCell;
+use crate::heap::{AllocError, Heap};
+use crate::slot::Registry;
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+
+#[cfg(test)]
+mod tests;
+
+/// Allocator which keeps track of freed memory and reuses it.
+///
+/// Just like [`BumpAllocator`](`crate::BumpAllocator`), the allocator starts
+/// at HEAP_START, grows upward and supports reserving space for global state
+/// `G` at the start of the heap.  Unlike the bump allocator, it keeps a list of
+/// free chunks sorted by address.  Freed chunks are merged with their free
+/// neighbours and chunks adjacent to the end of used memory are returned to
+/// the unused part of the heap.  New allocations are taken from the first free
+/// chunk they fit in.
+///
+/// Each allocation is preceded by a pointer-sized word and chunks are rounded
+/// to 16 bytes.  Allocation and deallocation take time linear in the number of
+/// free chunks.
+pub struct FreeListAllocator<G> {
+    heap: Heap,
+    _ph: core::marker::PhantomData<G>,
+}
+
+
+/// Granularity of chunks.  Start and end of each chunk are aligned to this.
+///
+/// This is also the minimum size of a chunk which is large enough to hold
+/// [`FreeChunk`].
+const GRANULE: usize = 16;
+
+/// Size of the word in front of each allocation which holds address of the
+/// start of its chunk.
+const WORD: usize = core::mem::size_of::<*mut u8>();
+
+const _: () = assert!(core::mem::size_of::<FreeChunk>() <= GRANULE);
+const _: () = assert!(core::mem::align_of::<FreeChunk>() <= GRANULE);
+
+
+/// Data stored by the [`FreeListAllocator`] at the start of the heap.
+struct Header<G> {
+    /// End of used memory.  Null if nothing has been allocated yet.
+    end_pos: Cell<*mut u8>,
+    /// End of the heap declared at run time or null if it hasn’t been.
+    heap_end: Cell<*mut u8>,
+    /// Global slots declared with `global_slot` macro.
+    slots: Registry,
+    /// First free chunk.  Chunks are sorted by address.
+    free: Cell<*mut FreeChunk>,
+    /// Allocation statistics.
+    #[cfg(feature = "stats")]
+    stats: Cell<Stats>,
+    global: GlobalCell<G>,
+}
+
+// SAFETY: All fields are Zeroable; pointers are null when zeroed.
+unsafe impl<G: bytemuck::Zeroable> bytemuck::Zeroable for Header<G> {}
+
+/// Free chunk of memory.  Written at the start of the chunk.
+struct FreeChunk {
+    /// End of the chunk.
+    end: *mut u8,
+    /// Next free chunk at higher address or null.
+    next: *mut FreeChunk,
+}
+
+
+#[cfg(all(fixed_heap, not(test)))]
+impl<G> FreeListAllocator<G> {
+    /// Creates a new global allocator.
+    ///
+    /// # Safety
+    ///
+    /// Caller may instantiate only one allocator and must set it as a global
+    /// allocator.  See [`BumpAllocator::new`](`crate::BumpAllocator::new`).
+    pub const unsafe fn new() -> Self {
+        // SAFETY: Caller upholds the same requirements.
+        unsafe { Self::with_heap_size(0) }
+    }
+
+    /// Creates a new global allocator for heap of given size.
+    ///
+    /// See [`BumpAllocator::with_heap_size`](`crate::BumpAllocator::with_heap_size`).
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`Self::new`].
+    pub const unsafe fn with_heap_size(size: usize) -> Self {
+        Self { heap: Heap::new(size), _ph: core::marker::PhantomData }
+    }
+}
+
+#[cfg(any(not(fixed_heap), test))]
+impl<G: bytemuck::Zeroable> FreeListAllocator<G> {
+    /// Creates a new allocator operating on a zero-initialised heap of given
+    /// size allocated with the global allocator.
+    ///
+    /// See [`BumpAllocator::with_capacity`](`crate::BumpAllocator::with_capacity`).
+    pub fn with_capacity(size: usize) -> Self {
+        let this =
+            Self { heap: Heap::new(size), _ph: core::marker::PhantomData };
+        this.header();
+        this
+    }
+
+    /// Returns start of the heap.
+    #[cfg(feature = "trace")]
+    pub(crate) fn heap_start(&self) -> *mut u8 { self.heap.start() }
+}
+
+impl<G: bytemuck::Zeroable> FreeListAllocator<G> {
+    /// Returns reference to allocator’s internal data stored at the front of
+    /// the heap.
+    fn header(&self) -> &Header<G> { self.heap.header() }
+
+    /// Returns address at which allocations start, i.e. end of the header
+    /// aligned to chunk granularity.
+    fn start_pos(&self, header: &Header<G>) -> *mut u8 {
+        let ptr = crate::ptr::with_addr(
+            self.heap.start(),
+            crate::ptr::end_addr_of_val(header),
+        );
+        crate::ptr::align(ptr, GRANULE)
+    }
+
+    /// Returns end of used memory.
+    fn end_pos(&self, header: &Header<G>) -> *mut u8 {
+        let ptr = header.end_pos.get();
+        // On first call, end_pos is null.  Start allocating past the header.
+        if ptr.is_null() {
+            self.start_pos(header)
+        } else {
+            ptr
+        }
+    }
+
+    /// Returns reference to global state `G` reserved on the heap.
+    ///
+    /// See [`BumpAllocator::global`](`crate::BumpAllocator::global`).
+    #[track_caller]
+    pub fn global(&self) -> &G { self.header().global.get() }
+
+    /// Calls `f` with exclusive reference to global state `G`.
+    ///
+    /// See [`BumpAllocator::with_global_mut`](`crate::BumpAllocator::with_global_mut`).
+    #[track_caller]
+    pub fn with_global_mut<R>(&self, f: impl FnOnce(&mut G) -> R) -> R {
+        self.try_with_global_mut(f).expect("global state already borrowed")
+    }
+
+    /// Calls `f` with exclusive reference to global state `G` or returns
+    /// `None` if the global state is already borrowed.
+    ///
+    /// See [`BumpAllocator::with_global_mut`](`crate::BumpAllocator::with_global_mut`).
+    pub fn try_with_global_mut<R>(
+        &self,
+        f: impl FnOnce(&mut G) -> R,
+    ) -> Option<R> {
+        self.header().global.try_with_mut(f)
+    }
+
+    /// Returns registry of global slots.  Used by the macros.
+    #[doc(hidden)]
+    pub fn registry(&self) -> &Registry { &self.header().slots }
+
+    /// Declares size of the heap at run time.
+    ///
+    /// See [`BumpAllocator::set_heap_size`](`crate::BumpAllocator::set_heap_size`).
+    pub fn set_heap_size(&self, size: usize) {
+        self.header().heap_end.set(self.heap.heap_end(size));
+    }
+
+    /// Declares size of the heap based on the Instructions sysvar.
+    ///
+    /// See [`BumpAllocator::set_heap_size_from_sysvar`](`crate::BumpAllocator::set_heap_size_from_sysvar`).
+    pub fn set_heap_size_from_sysvar(
+        &self,
+        key: &[u8],
+        data: &[u8],
+    ) -> Option<usize> {
+        if key != crate::sysvar::INSTRUCTIONS_SYSVAR_ID {
+            return None;
+        }
+        let size = crate::sysvar::requested_heap_frame(data)?;
+        let size = usize::try_from(size).ok()?;
+        self.set_heap_size(size);
+        Some(size)
+    }
+
+    /// Returns number of bytes available for allocation at the end of the
+    /// heap or `None` if heap size isn’t known.
+    ///
+    /// This doesn’t include free chunks on the free list.  See
+    /// [`BumpAllocator::remaining`](`crate::BumpAllocator::remaining`).
+    pub fn remaining(&self) -> Option<usize> {
+        let header = self.header();
+        let end = self.heap.known_end(header.heap_end.get())?;
+        Some((end as usize).saturating_sub(self.end_pos(header) as usize))
+    }
+
+    /// Returns allocation statistics.
+    ///
+    /// Memory on the free list counts as used and deallocations are never
+    /// leaked.  Padding includes rounding of chunks to 16 bytes.  See
+    /// [`BumpAllocator::stats`](`crate::BumpAllocator::stats`).
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        let header = self.header();
+        let start = self.start_pos(header) as usize;
+        let used = self.end_pos(header) as usize - start;
+        let reserved = start - self.heap.start() as usize;
+        Stats { used, reserved, ..header.stats.get() }
+    }
+
+    /// Resets peak heap usage reported by [`Self::stats`] to current usage.
+    ///
+    /// See [`BumpAllocator::reset_peak`](`crate::BumpAllocator::reset_peak`).
+    #[cfg(feature = "stats")]
+    pub fn reset_peak(&self) {
+        let used = self.stats().used;
+        Stats::update(&self.header().stats, |stats| stats.peak = used);
+    }
+
+    /// Allocates memory as described by `layout`.
+    ///
+    /// See [`BumpAllocator::try_alloc`](`crate::BumpAllocator::try_alloc`).
+    pub fn try_alloc(
+        &self,
+        layout: Layout,
+    ) -> Result<core::ptr::NonNull<u8>, AllocError> {
+        // SAFETY: Our alloc handles zero-sized layouts.
+        let ptr = unsafe { self.alloc(layout) };
+        core::ptr::NonNull::new(ptr).ok_or(AllocError::new(layout))
+    }
+
+    /// Inserts chunk `[start, end)` into the free list merging it with
+    /// adjacent free chunks and end of used memory.
+    ///
+    /// # Safety
+    ///
+    /// The chunk must be unused and not overlap any free chunk.
+    unsafe fn free_chunk(
+        &self,
+        header: &Header<G>,
+        mut start: *mut u8,
+        mut end: *mut u8,
+    ) {
+        // Find the last chunk before `start` (`prev`), the chunk before it
+        // (`pprev`) and the first chunk after `start` (`next`).
+        let mut pprev: *mut FreeChunk = core::ptr::null_mut();
+        let mut prev: *mut FreeChunk = core::ptr::null_mut();
+        let mut next = header.free.get();
+        while !next.is_null() && next.cast::<u8>() < start {
+            pprev = prev;
+            prev = next;
+            // SAFETY: All chunks on the list are valid.
+            next = unsafe { (*next).next };
+        }
+
+        // Merge with following chunk.
+        if !next.is_null() && next.cast::<u8>() == end {
+            // SAFETY: All chunks on the list are valid.
+            (end, next) = unsafe { ((*next).end, (*next).next) };
+        }
+        // Merge with preceding chunk.
+        // SAFETY: All chunks on the list are valid.
+        if unsafe { prev.as_ref() }.is_some_and(|chunk| chunk.end == start) {
+            start = prev.cast();
+            prev = pprev;
+        }
+
+        if end == header.end_pos.get() {
+            // The chunk is at the end of used memory.  Return it to the
+            // unused part of the heap.
+            header.end_pos.set(start);
+            self.link(header, prev, next);
+        } else {
+            let chunk = start.cast::<FreeChunk>();
+            // SAFETY: start is aligned to GRANULE and chunk is at least
+            // GRANULE bytes large.
+            unsafe { chunk.write(FreeChunk { end, next }) };
+            self.link(header, prev, chunk);
+        }
+    }
+
+    /// Sets `prev`’s next pointer (or head of the list if `prev` is null) to
+    /// `next`.
+    fn link(
+        &self,
+        header: &Header<G>,
+        prev: *mut FreeChunk,
+        next: *mut FreeChunk,
+    ) {
+        // SAFETY: All chunks on the list are valid.
+        match unsafe { prev.as_mut() } {
+            Some(prev) => prev.next = next,
+            None => header.free.set(next),
+        }
+    }
+
+    /// Places allocation in chunk starting at `start`.  Returns pointer to
+    /// data and end of the chunk or `None` if address calculation overflows.
+    fn place(start: *mut u8, layout: Layout) -> Option<(*mut u8, *mut u8)> {
+        let data = start.wrapping_add(WORD);
+        let data = crate::ptr::align(data, layout.align().max(WORD));
+        let end = (data as usize).checked_add(layout.size())?;
+        let end = end.checked_add(GRANULE - 1)? & !(GRANULE - 1);
+        Some((data, crate::ptr::with_addr(data, end)))
+    }
+
+    /// Writes chunk start in front of the data and returns the data pointer.
+    fn finish(data: *mut u8, start: *mut u8) -> *mut u8 {
+        // SAFETY: data is aligned to at least WORD and there’s at least WORD
+        // bytes of the chunk in front of it.
+        unsafe { data.sub(WORD).cast::<*mut u8>().write(start) };
+        data
+    }
+
+    /// Records allocation of chunk starting at `start` in the statistics.
+    #[cfg(feature = "stats")]
+    fn record_alloc(&self, header: &Header<G>, start: *mut u8, layout: Layout) {
+        let end = Self::place(start, layout).unwrap().1;
+        let padding = end as usize - start as usize - WORD - layout.size();
+        let used =
+            self.end_pos(header) as usize - self.start_pos(header) as usize;
+        Stats::record_use(&header.stats, used, padding);
+        Stats::update(&header.stats, |stats| {
+            stats.allocs += 1;
+            stats.live_bytes += layout.size();
+        });
+    }
+}
+
+unsafe impl<G: bytemuck::Zeroable> GlobalAlloc for FreeListAllocator<G> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let header = self.header();
+
+        // Look for the first free chunk the allocation fits in.
+        let mut prev: *mut FreeChunk = core::ptr::null_mut();
+        let mut it = header.free.get();
+        // SAFETY: All chunks on the list are valid.
+        while let Some(chunk) = unsafe { it.as_ref() } {
+            let start = it.cast::<u8>();
+            if let Some((data, end)) = Self::place(start, layout) {
+                if end <= chunk.end {
+                    let next = if end < chunk.end {
+                        // Put the remainder back on the list.
+                        let rest = end.cast::<FreeChunk>();
+                        let remainder =
+                            FreeChunk { end: chunk.end, next: chunk.next };
+                        // SAFETY: end is aligned to GRANULE and there are at
+                        // least GRANULE bytes in the remainder.
+                        unsafe { rest.write(remainder) };
+                        rest
+                    } else {
+                        chunk.next
+                    };
+                    self.link(header, prev, next);
+                    #[cfg(feature = "stats")]
+                    self.record_alloc(header, start, layout);
+                    return Self::finish(data, start);
+                }
+            }
+            prev = it;
+            it = chunk.next;
+        }
+
+        // Allocate from unused part of the heap.
+        let start = self.end_pos(header);
+        let Some((data, end)) = Self::place(start, layout) else {
+            return core::ptr::null_mut();
+        };
+        let size = end as usize - start as usize;
+        match self.heap.reserve(start, size, header.heap_end.get()) {
+            Some(end) => {
+                header.end_pos.set(end);
+                #[cfg(feature = "stats")]
+                self.record_alloc(header, start, layout);
+                Self::finish(data, start)
+            }
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: Caller guarantees ptr has been returned by us.
+        let start = unsafe { ptr.sub(WORD).cast::<*mut u8>().read() };
+        let end = Self::place(start, layout).unwrap().1;
+        let header = self.header();
+        #[cfg(feature = "stats")]
+        Stats::update(&header.stats, |stats| {
+            stats.deallocs += 1;
+            stats.reclaimed += 1;
+            stats.live_bytes -= layout.size();
+        });
+        // SAFETY: Caller guarantees ptr has been returned by us and thus
+        // [start, end) is a used chunk.
+        unsafe { self.free_chunk(header, start, end) }
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // SAFETY: Caller guarantees new layout is valid.
+        let new_layout = unsafe {
+            Layout::from_size_align_unchecked(new_size, layout.align())
+        };
+        let header = self.header();
+        #[cfg(feature = "stats")]
+        Stats::update(&header.stats, |stats| stats.reallocs += 1);
+        // SAFETY: Caller guarantees ptr has been returned by us.
+        let start = unsafe { ptr.sub(WORD).cast::<*mut u8>().read() };
+        let end = Self::place(start, layout).unwrap().1;
+        let Some((_, new_end)) = Self::place(start, new_layout) else {
+            return core::ptr::null_mut();
+        };
+
+        #[cfg(feature = "stats")]
+        let record_resize = || {
+            Stats::update(&header.stats, |stats| {
+                stats.live_bytes = stats.live_bytes - layout.size() + new_size
+            })
+        };
+
+        if new_end <= end {
+            // Shrinking.  Free the tail of the chunk (if any).
+            if new_end < end {
+                // SAFETY: [new_end, end) is part of our chunk.
+                unsafe { self.free_chunk(header, new_end, end) };
+            }
+            #[cfg(feature = "stats")]
+            record_resize();
+            return ptr;
+        }
+
+        if end == header.end_pos.get() {
+            // Last chunk can be grown in place.  If the heap is too small,
+            // the allocation may still fit in a free chunk.
+            let size = new_end as usize - end as usize;
+            let heap_end = header.heap_end.get();
+            if let Some(new_end) = self.heap.reserve(end, size, heap_end) {
+                header.end_pos.set(new_end);
+                #[cfg(feature = "stats")]
+                Stats::record_use(
+                    &header.stats,
+                    new_end as usize - self.start_pos(header) as usize,
+                    0,
+                );
+                #[cfg(feature = "stats")]
+                record_resize();
+                return ptr;
+            }
+        }
+
+        // Otherwise, we need to make a new allocation and copy.
+        // SAFETY: Caller guarantees new layout has non-zero size.
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            // SAFETY: ptr is still allocated so new chunk cannot overlap it.
+            unsafe { crate::ptr::memcpy(new_ptr, ptr, layout.size()) };
+            // SAFETY: Caller guarantees ptr is a live allocation.
+            unsafe { self.dealloc(ptr, layout) };
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,268 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+
+use crate::heap::Heap;
+use crate::{ptr, FreeListAllocator};
+
+impl<G: bytemuck::Zeroable> FreeListAllocator<G> {
+    /// Creates a new allocator with given amount of available memory.
+    pub(crate) fn new(size: usize) -> Self {
+        Self { heap: Heap::new(size), _ph: core::marker::PhantomData }
+    }
+
+    /// Returns amount of used memory in bytes excluding space used by the
+    /// header stored at the start of the heap.
+    fn used(&self) -> usize {
+        let header = self.header();
+        let start = self.start_pos(header) as usize;
+        (self.end_pos(header) as usize) - start
+    }
+
+    /// Returns number of chunks and total number of bytes on the free list.
+    fn free(&self) -> (usize, usize) {
+        let mut count = 0;
+        let mut size = 0;
+        let mut it = self.header().free.get();
+        while let Some(chunk) = unsafe { it.as_ref() } {
+            count += 1;
+            size += chunk.end as usize - it as usize;
+            it = chunk.next;
+        }
+        (count, size)
+    }
+
+    /// Allocates region of memory; checks returned alignment.
+    fn check_alloc(&self, layout: Layout) -> Option<*mut u8> {
+        core::ptr::NonNull::new(unsafe { self.alloc(layout) }).map(|ptr| {
+            let ptr = ptr.as_ptr();
+            let mask = layout.align() - 1;
+            assert_eq!(0, ptr as usize & mask, "{ptr:?} is misaligned");
+            ptr
+        })
+    }
+}
+
+#[test]
+fn test_alloc() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    assert_eq!(0, allocator.used());
+
+    // Large allocation fails.
+    let large = Layout::from_size_align(256, 1).unwrap();
+    assert_eq!(None, allocator.check_alloc(large));
+
+    // Each allocation takes a word in front of it and is rounded to 16 bytes.
+    let layout_align_1 = Layout::from_size_align(9, 1).unwrap();
+    let layout_align_32 = Layout::from_size_align(8, 32).unwrap();
+
+    let first = allocator.check_alloc(layout_align_1).unwrap();
+    assert_eq!(32, allocator.used());
+
+    let second = allocator.check_alloc(layout_align_32).unwrap();
+    ptr::assert_no_overlap(first, 9, second, 8);
+    assert!(allocator.used() <= 32 + 48, "{}", allocator.used());
+}
+
+#[test]
+fn test_reuse() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    let layout = Layout::array::<u8>(8).unwrap();
+
+    let first = allocator.check_alloc(layout).unwrap();
+    let second = allocator.check_alloc(layout).unwrap();
+    let third = allocator.check_alloc(layout).unwrap();
+    assert_eq!(48, allocator.used());
+
+    // Freeing from the middle puts the chunk on the free list…
+    unsafe { allocator.dealloc(first, layout) };
+    assert_eq!((1, 16), allocator.free());
+    assert_eq!(48, allocator.used());
+
+    // …and it’s reused by subsequent allocation.
+    assert_eq!(first, allocator.check_alloc(layout).unwrap());
+    assert_eq!((0, 0), allocator.free());
+
+    // Adjacent free chunks are merged.
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(second, layout);
+    }
+    assert_eq!((1, 32), allocator.free());
+
+    // Larger allocation fits in the merged chunk.
+    let large = Layout::array::<u8>(24).unwrap();
+    assert_eq!(first, allocator.check_alloc(large).unwrap());
+    assert_eq!((0, 0), allocator.free());
+    unsafe { allocator.dealloc(first, large) };
+
+    // Freeing the last chunk returns it and all adjacent free chunks to the
+    // unused part of the heap.
+    unsafe { allocator.dealloc(third, layout) };
+    assert_eq!((0, 0), allocator.free());
+    assert_eq!(0, allocator.used());
+}
+
+#[test]
+fn test_split() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    let large = Layout::array::<u8>(40).unwrap();
+    let small = Layout::array::<u8>(8).unwrap();
+
+    let first = allocator.check_alloc(large).unwrap();
+    let _second = allocator.check_alloc(small).unwrap();
+    unsafe { allocator.dealloc(first, large) };
+    assert_eq!((1, 48), allocator.free());
+
+    // Small allocation takes part of the free chunk.
+    assert_eq!(first, allocator.check_alloc(small).unwrap());
+    assert_eq!((1, 32), allocator.free());
+    let third = allocator.check_alloc(small).unwrap();
+    assert_eq!((1, 16), allocator.free());
+    ptr::assert_no_overlap(first, 8, third, 8);
+}
+
+#[test]
+fn test_realloc() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    let layout_8 = Layout::array::<u8>(8).unwrap();
+    let layout_40 = Layout::array::<u8>(40).unwrap();
+
+    // Last chunk grows in place.
+    let first = allocator.check_alloc(layout_8).unwrap();
+    unsafe { first.write_bytes(42, 8) };
+    assert_eq!(first, unsafe { allocator.realloc(first, layout_8, 40) });
+    assert_eq!(48, allocator.used());
+
+    // Shrinking frees tail of the chunk.
+    let second = allocator.check_alloc(layout_8).unwrap();
+    assert_eq!(first, unsafe { allocator.realloc(first, layout_40, 8) });
+    assert_eq!((1, 32), allocator.free());
+
+    // Growing chunk in the middle moves it.
+    let moved = unsafe { allocator.realloc(first, layout_8, 40) };
+    assert_ne!(first, moved);
+    assert_eq!([42; 8], unsafe { *moved.cast::<[u8; 8]>() });
+    ptr::assert_no_overlap(second, 8, moved, 40);
+
+    unsafe {
+        allocator.dealloc(moved, layout_40);
+        allocator.dealloc(second, layout_8);
+    }
+    assert_eq!((0, 0), allocator.free());
+    assert_eq!(0, allocator.used());
+}
+
+#[test]
+fn test_realloc_last_full() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    let layout_8 = Layout::array::<u8>(8).unwrap();
+    let layout_40 = Layout::array::<u8>(40).unwrap();
+
+    let first = allocator.check_alloc(layout_40).unwrap();
+    let second = allocator.check_alloc(layout_8).unwrap();
+    unsafe { second.write_bytes(42, 8) };
+    unsafe { allocator.dealloc(first, layout_40) };
+    let header = allocator.header();
+    let size =
+        allocator.end_pos(header) as usize - allocator.heap.start() as usize;
+    allocator.set_heap_size(size);
+
+    // Last chunk cannot grow past the end of the heap but the free chunk in
+    // front of it is large enough.
+    let moved = unsafe { allocator.realloc(second, layout_8, 40) };
+    assert_eq!(first, moved);
+    assert_eq!([42; 8], unsafe { *moved.cast::<[u8; 8]>() });
+    unsafe { allocator.dealloc(moved, layout_40) };
+    assert_eq!(0, allocator.used());
+}
+
+#[test]
+fn test_global() {
+    let allocator = FreeListAllocator::<Cell<usize>>::new(256);
+
+    let global = allocator.global();
+    assert_eq!(0, global.get());
+    global.set(42);
+
+    let layout = Layout::from_size_align(8, 1).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    ptr::assert_no_overlap(
+        core::ptr::addr_of!(*global).cast(),
+        core::mem::size_of_val(global),
+        ptr,
+        8,
+    );
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(42, allocator.global().get());
+}
+
+#[test]
+fn test_with_global_mut() {
+    let allocator = FreeListAllocator::<[u64; 4]>::new(256);
+    allocator.with_global_mut(|global| global[1] = 42);
+    assert_eq!(
+        Some(42),
+        allocator.try_with_global_mut(|global| {
+            assert_eq!(None, allocator.try_with_global_mut(|_| ()));
+            global[1]
+        })
+    );
+    assert_eq!([0, 42, 0, 0], *allocator.global());
+    assert_eq!(None, allocator.try_with_global_mut(|_| ()));
+}
+
+#[test]
+fn test_heap_size() {
+    let allocator = FreeListAllocator::<()>::new(256);
+    let layout = Layout::array::<u8>(8).unwrap();
+
+    let first = allocator.try_alloc(layout).unwrap().as_ptr();
+    let header = allocator.header();
+    let size =
+        allocator.end_pos(header) as usize - allocator.heap.start() as usize;
+    allocator.set_heap_size(size);
+    assert_eq!(layout, allocator.try_alloc(layout).unwrap_err().layout());
+
+    // Freed chunks can still be reused.
+    unsafe { allocator.dealloc(first, layout) };
+    assert_eq!(first, allocator.try_alloc(layout).unwrap().as_ptr());
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats() {
+    let allocator = FreeListAllocator::<()>::new(1024);
+    let stats = allocator.stats();
+    assert!(stats.reserved >= core::mem::size_of::<*mut u8>());
+    let reserved = stats.reserved;
+    assert_eq!(crate::Stats { reserved, ..Default::default() }, stats);
+
+    // Each allocation takes a word in front of it and is rounded to 16 bytes
+    // so 10-byte allocation has 14 bytes of padding.
+    let layout = Layout::from_size_align(10, 1).unwrap();
+    let first = allocator.check_alloc(layout).unwrap();
+    let second = allocator.check_alloc(layout).unwrap();
+    let stats = allocator.stats();
+    assert_eq!((2, 0, 0), (stats.allocs, stats.deallocs, stats.reallocs));
+    assert_eq!((64, 64, 28), (stats.used, stats.peak, stats.padding));
+    assert_eq!(
+        (2, 20, 44),
+        (stats.live(), stats.live_bytes, stats.unreclaimed())
+    );
+
+    // Deallocations are never leaked.
+    unsafe { allocator.dealloc(first, layout) };
+    unsafe { allocator.dealloc(second, layout) };
+    let stats = allocator.stats();
+    assert_eq!((2, 2, 0), (stats.deallocs, stats.reclaimed, stats.leaked));
+    assert_eq!((0, 0), (stats.live(), stats.live_bytes));
+    assert_eq!((0, 64), (stats.used, stats.peak));
+
+    // Growing the last chunk in place updates peak usage.
+    let ptr = allocator.check_alloc(layout).unwrap();
+    unsafe { allocator.realloc(ptr, layout, 100) };
+    let stats = allocator.stats();
+    assert_eq!((3, 1), (stats.allocs, stats.reallocs));
+    assert_eq!((112, 112), (stats.used, stats.peak));
+    assert_eq!((1, 100), (stats.live(), stats.live_bytes));
+}
@@ -0,0 +1,256 @@
+//! Description of the heap memory region shared by the allocators.
+
+use alloc::alloc::Layout;
+
+/// Heap memory region the allocators operate on.
+///
+/// When building for Solana (or for Linux with `sbf-memory-map` Cargo
+/// feature), all the addresses are compile-time constants and the only state
+/// is the declared heap size.  Otherwise (i.e. in unit tests or with `testing`
+/// Cargo feature), the heap is a buffer allocated with the global allocator.
+pub(crate) struct Heap {
+    /// Declared size of the heap or zero if unknown.
+    #[cfg(all(fixed_heap, not(test)))]
+    size: usize,
+    #[cfg(any(not(fixed_heap), test))]
+    ptr: core::ptr::NonNull<u8>,
+    #[cfg(any(not(fixed_heap), test))]
+    layout: Layout,
+}
+
+
+/// Start address of the memory region used for program heap.
+///
+/// This is the same as `solana_sdk::entrypoint::HEAP_START_ADDRESS`.
+#[cfg(all(fixed_heap, not(test)))]
+pub(crate) const HEAP_START_ADDRESS: u64 = 0x3_0000_0000;
+
+/// Minimal length of the heap memory region used for program heap.
+///
+/// The actual heap size may be larger if Compute Budget Program’s
+/// `RequestHeapFrame` instruction was used.
+///
+/// This is the same as `solana_sdk::entrypoint::HEAP_LENGTH`.
+#[cfg(all(fixed_heap, not(test)))]
+const HEAP_LENGTH: usize = 32 * 1024;
+
+/// Start address of the memory region where program input parameters are
+/// stored.
+///
+/// See <https://solana.com/docs/programs/faq#memory-map>.
+#[cfg(all(fixed_heap, not(test)))]
+const PROGRAM_INPUT_ADDRESS: u64 = 0x4_0000_0000;
+
+/// Largest heap size which can be declared.  Larger sizes would make the heap
+/// overlap the program input region.
+#[cfg(all(fixed_heap, not(test)))]
+const MAX_SIZE: usize = (PROGRAM_INPUT_ADDRESS - HEAP_START_ADDRESS) as usize;
+
+
+#[cfg(all(fixed_heap, not(test)))]
+impl Heap {
+    /// Returns the heap provided by Solana runtime.
+    ///
+    /// `size` is the declared heap size.  If it’s zero, the size is unknown
+    /// and the allocators assume the heap extends up to the program input
+    /// region.  Sizes past the program input region are capped.
+    pub const fn new(size: usize) -> Self {
+        Self { size: if size > MAX_SIZE { MAX_SIZE } else { size } }
+    }
+
+    /// Returns start of the heap.
+    pub const fn start(&self) -> *mut u8 { HEAP_START_ADDRESS as *mut u8 }
+
+    /// Returns safe end of the heap, i.e. end of a region that is guaranteed to
+    /// be valid heap.
+    ///
+    /// Unless heap size has been declared, we don’t know the actual Solana heap
+    /// size so this is limited to just 32 KiB when running on Solana (which is
+    /// guaranteed minimum heap size).
+    pub const fn safe_end(&self) -> *mut u8 {
+        let size = if self.size == 0 { HEAP_LENGTH } else { self.size };
+        (HEAP_START_ADDRESS + size as u64) as *mut u8
+    }
+
+    /// Returns the address at which there’s definitely no heap.
+    ///
+    /// This is end of the heap if its size has been declared or start of the
+    /// program input region otherwise.
+    pub const fn limit(&self) -> *mut u8 {
+        if self.size == 0 {
+            PROGRAM_INPUT_ADDRESS as *mut u8
+        } else {
+            (HEAP_START_ADDRESS + self.size as u64) as *mut u8
+        }
+    }
+
+    /// Returns end of the heap if its size has been declared.
+    ///
+    /// `heap_end` is end of the heap set at run time or null if it hasn’t been
+    /// set.  See [`Self::end`].
+    pub fn known_end(&self, heap_end: *mut u8) -> Option<*mut u8> {
+        if !heap_end.is_null() {
+            Some(heap_end)
+        } else if self.size != 0 {
+            Some(self.limit())
+        } else {
+            None
+        }
+    }
+
+    /// Returns end of the memory region the heap may occupy, i.e. start of the
+    /// program input region.
+    const fn max_end(&self) -> *mut u8 { PROGRAM_INPUT_ADDRESS as *mut u8 }
+}
+
+#[cfg(any(not(fixed_heap), test))]
+impl Heap {
+    /// Allocates a new zero-initialised heap of given size.
+    ///
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> Self {
+        assert_ne!(0, size, "heap size must be non-zero");
+        let layout = Layout::from_size_align(
+            size,
+            core::mem::align_of::<core::cell::Cell<usize>>(),
+        )
+        .unwrap();
+        // SAFETY: We’ve checked above that size of the layout is non-zero.
+        // Allocation failure (i.e. null pointer) is handled below.
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let ptr = core::ptr::NonNull::new(ptr)
+            .unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+
+    pub fn start(&self) -> *mut u8 { self.ptr.as_ptr() }
+    pub fn safe_end(&self) -> *mut u8 {
+        self.start().wrapping_add(self.layout.size())
+    }
+    pub fn limit(&self) -> *mut u8 { self.safe_end() }
+    fn max_end(&self) -> *mut u8 { self.safe_end() }
+    pub fn known_end(&self, heap_end: *mut u8) -> Option<*mut u8> {
+        Some(self.end(heap_end))
+    }
+}
+
+impl Heap {
+    /// Returns reference to allocator’s header stored at the front of the
+    /// heap.
+    ///
+    /// Panics if the header doesn’t fit in the safe portion of the heap.
+    pub fn header<H: bytemuck::Zeroable>(&self) -> &H {
+        // In release build on Solana, all of those numbers are known at compile
+        // time so all this maths should be compiled out.
+        let ptr = crate::ptr::align(self.start(), core::mem::align_of::<H>());
+        // Make sure that the header does not go past the safe portion of the
+        // heap (i.e. portion we are guaranteed to be accessible).
+        let end = ptr.wrapping_add(core::mem::size_of::<H>());
+        assert!(end <= self.safe_end(), "Global state too large");
+        // SAFETY: 1. `ptr` is properly aligned and points to region within heap
+        // owned by us.  2. The heap has been zero-initialised and H is
+        // Zeroable.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns end of the heap set at run time (see [`Self::heap_end`]) if it’s
+    /// non-null or [`Self::limit`] otherwise.
+    pub fn end(&self, heap_end: *mut u8) -> *mut u8 {
+        if heap_end.is_null() {
+            self.limit()
+        } else {
+            heap_end
+        }
+    }
+
+    /// Returns end of the heap of given size or null if `size` is zero.
+    ///
+    /// This is meant for allocators to store in their header when heap size is
+    /// declared at run time.  Value returned here can then be passed to
+    /// [`Self::end`] and [`Self::reserve`].  The end is capped so that the heap
+    /// never extends past memory region reserved for it (i.e. into program
+    /// input region on Solana or past the buffer otherwise).
+    pub fn heap_end(&self, size: usize) -> *mut u8 {
+        if size == 0 {
+            core::ptr::null_mut()
+        } else {
+            let max = self.max_end() as usize - self.start() as usize;
+            self.start().wrapping_add(size.min(max))
+        }
+    }
+
+    /// Checks whether region of given size starting at `ptr` falls within
+    /// available heap space and returns its end if it does.
+    ///
+    /// `heap_end` is end of the heap set at run time or null if it hasn’t been
+    /// set.  See [`Self::end`].
+    ///
+    /// When the heap is at a fixed address, the check is done by writing zero
+    /// byte to the last byte of the slice which will cause UB if it fails
+    /// beyond available heap space.
+    ///
+    /// When run as Solana contract (or with heap mapped by
+    /// [`crate::mmap::map_heap`]) that UB is segfault.  If `poke` Cargo
+    /// feature is enabled, the segfault happens when trying to allocate; by
+    /// default it’s deferred to the moment region past the heap is accessed by
+    /// the client (a bit like over-committing works in Linux).
+    pub fn reserve(
+        &self,
+        ptr: *mut u8,
+        size: usize,
+        heap_end: *mut u8,
+    ) -> Option<*mut u8> {
+        let limit = self.end(heap_end);
+        (ptr as usize)
+            .checked_add(size)
+            .map(|addr| crate::ptr::with_addr(ptr, addr))
+            .filter(|&end| end <= limit)
+            .inspect(|&end| {
+                if cfg!(all(fixed_heap, not(test))) && cfg!(feature = "poke") {
+                    // SAFETY: This is unsound but it will only execute on
+                    // Solana (or with Solana memory map reproduced on Linux)
+                    // where accessing memory beyond heap results in segfault
+                    // which is what we want.
+                    let _ = unsafe { end.sub(1).read_volatile() };
+                }
+            })
+    }
+}
+
+#[cfg(any(not(fixed_heap), test))]
+impl core::ops::Drop for Heap {
+    fn drop(&mut self) {
+        // SAFETY: ptr and layout are the same as when we’ve allocated.
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+
+/// The memory allocation failed.
+///
+/// Returned by `try_alloc` methods of the allocators when there’s not enough
+/// memory on the heap to satisfy the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError {
+    layout: Layout,
+}
+
+impl AllocError {
+    pub(crate) fn new(layout: Layout) -> Self { Self { layout } }
+
+    /// Returns layout of the allocation which failed.
+    pub fn layout(&self) -> Layout { self.layout }
+}
+
+impl core::fmt::Display for AllocError {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            fmtr,
+            "memory allocation of {} bytes (aligned to {}) failed",
+            self.layout.size(),
+            self.layout.align()
+        )
+    }
+}
+
+impl core::error::Error for AllocError {}
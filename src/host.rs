@@ -0,0 +1,404 @@
+//! Emulation of Solana heap limits when running programs natively.
+//!
+//! When a program runs natively (e.g. as a builtin in `solana-program-test`),
+//! it uses the system allocator and never runs out of memory even if it would
+//! on chain.  [`HostAllocator`] is a global allocator which mirrors on-chain
+//! heap usage of each program invocation and reports allocations which would
+//! fail on chain.
+//!
+//! The allocator hands out memory from the system allocator.  Additionally,
+//! while an invocation is active (see [`HostAllocator::invoke`]), each
+//! allocation is also made on a shadow heap of the on-chain size managed by
+//! the allocator used by [`custom_heap`](`crate::custom_heap`) and
+//! [`custom_global`](`crate::custom_global`) macros.  Allocation and freeing
+//! therefore behave the same way as on chain, including effects of enabled
+//! Cargo features.  When an allocation doesn’t fit on the shadow heap, it’s
+//! reported on standard error together with a backtrace.
+//!
+//! Available when not building for Solana with `testing` Cargo feature.
+//!
+//! # Example
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static A: solana_allocator::HostAllocator =
+//!     solana_allocator::HostAllocator::new(32 * 1024);
+//!
+//! fn process_instruction(
+//!     program_id: &Pubkey,
+//!     accounts: &[AccountInfo],
+//!     data: &[u8],
+//! ) -> ProgramResult {
+//!     A.invoke(|| my_program::process_instruction(program_id, accounts, data))
+//!         .map_err(|_| ProgramError::Custom(0xDEAD))?
+//! }
+//! ```
+
+use core::cell::Cell;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[cfg(test)]
+mod tests;
+
+/// Global allocator emulating on-chain heap limits.
+///
+/// `G` is type of the program’s global state (see
+/// [`custom_global`](`crate::custom_global`)) which occupies space at the
+/// start of the heap on chain.  See [module documentation](`self`) for
+/// details.
+pub struct HostAllocator<G = ()> {
+    size: usize,
+    _ph: core::marker::PhantomData<fn() -> G>,
+}
+
+/// Error returned by [`HostAllocator::invoke`] if an allocation would have
+/// failed on chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapExhausted {
+    layout: Layout,
+    used: usize,
+    size: usize,
+}
+
+/// Data stored in front of each block handed out by [`HostAllocator`].
+struct Prefix {
+    /// Invocation the block has been allocated in or zero if the block isn’t
+    /// on a shadow heap.
+    invocation: u64,
+    /// Address of the block on the shadow heap.
+    shadow: *mut u8,
+}
+
+/// Shadow heap of an invocation.
+trait Shadow {
+    fn alloc(&self, layout: Layout) -> *mut u8;
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8;
+    fn remaining(&self) -> usize;
+}
+
+/// State of the current thread’s active invocation.
+struct State {
+    /// Identifier of the invocation or zero if there’s none.
+    invocation: Cell<u64>,
+    /// Shadow heap of the invocation or `None` if there’s no invocation or
+    /// allocations are not being tracked.  The heap is owned by
+    /// [`HostAllocator::invoke`] which removes it from the state before
+    /// dropping it.
+    shadow: Cell<Option<NonNull<dyn Shadow>>>,
+    /// Heap size of the invocation.
+    size: Cell<usize>,
+    /// First allocation which didn’t fit on the shadow heap.
+    failure: Cell<Option<HeapExhausted>>,
+}
+
+std::thread_local! {
+    static STATE: State = const {
+        State {
+            invocation: Cell::new(0),
+            shadow: Cell::new(None),
+            size: Cell::new(0),
+            failure: Cell::new(None),
+        }
+    };
+}
+
+/// Identifier of the most recently started invocation.
+static LAST_INVOCATION: AtomicU64 = AtomicU64::new(0);
+
+
+impl<G> HostAllocator<G> {
+    /// Creates a new allocator emulating heap of given size.
+    pub const fn new(size: usize) -> Self {
+        Self { size, _ph: core::marker::PhantomData }
+    }
+}
+
+impl<G: bytemuck::Zeroable + 'static> HostAllocator<G> {
+    /// Executes `f` as a single program invocation.
+    ///
+    /// Allocations made by the current thread while `f` executes are tracked
+    /// on a fresh shadow heap.  If any of them wouldn’t fit in the on-chain
+    /// heap, it’s reported on standard error and, once `f` returns, error
+    /// describing the first such allocation is returned.  Note that the
+    /// allocation itself succeeds so `f` keeps running.
+    ///
+    /// Invocations can be nested (e.g. when a program invokes another program
+    /// natively) in which case each has its own shadow heap.
+    pub fn invoke<R>(&self, f: impl FnOnce() -> R) -> Result<R, HeapExhausted> {
+        /// Restores state of the outer invocation even if `f` panics.
+        struct Guard(
+            u64,
+            Option<NonNull<dyn Shadow>>,
+            usize,
+            Option<HeapExhausted>,
+        );
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                STATE.with(|state| {
+                    state.invocation.set(self.0);
+                    state.shadow.set(self.1);
+                    state.size.set(self.2);
+                    state.failure.set(self.3);
+                })
+            }
+        }
+
+        let invocation = LAST_INVOCATION.fetch_add(1, Ordering::Relaxed) + 1;
+        STATE.with(|state| {
+            // The shadow heap is allocated (and freed) without being tracked
+            // by the outer invocation.
+            let outer = state.shadow.take();
+            let shadow = crate::Allocator::<G>::with_capacity(self.size);
+            // Declared after shadow so it’s dropped first.
+            let _guard = Guard(
+                state.invocation.replace(invocation),
+                outer,
+                state.size.replace(self.size),
+                state.failure.replace(None),
+            );
+            let ptr = NonNull::from(&shadow as &(dyn Shadow + 'static));
+            state.shadow.set(Some(ptr));
+            let result = f();
+            state.failure.get().map_or(Ok(result), Err)
+        })
+    }
+}
+
+impl HeapExhausted {
+    /// Returns layout of the allocation which would have failed.
+    pub fn layout(&self) -> Layout { self.layout }
+
+    /// Returns number of heap bytes in use when the allocation was made.
+    pub fn used(&self) -> usize { self.used }
+
+    /// Returns size of the emulated heap.
+    pub fn heap_size(&self) -> usize { self.size }
+}
+
+impl fmt::Display for HeapExhausted {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmtr,
+            "allocation of {} bytes (aligned to {}) would fail on chain with \
+             {} of {} heap bytes in use",
+            self.layout.size(),
+            self.layout.align(),
+            self.used,
+            self.size,
+        )
+    }
+}
+
+impl core::error::Error for HeapExhausted {}
+
+
+impl<G: bytemuck::Zeroable> Shadow for crate::Allocator<G> {
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: Our allocators handle zero-sized layouts.
+        unsafe { GlobalAlloc::alloc(self, layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { GlobalAlloc::dealloc(self, ptr, layout) }
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { GlobalAlloc::realloc(self, ptr, layout, new_size) }
+    }
+
+    fn remaining(&self) -> usize {
+        // Heap size is always known for allocators created with with_capacity.
+        crate::Allocator::<G>::remaining(self).unwrap_or(0)
+    }
+}
+
+impl State {
+    /// Returns shadow heap of given invocation or `None` if it’s not the
+    /// active invocation or allocations are not being tracked.
+    fn shadow(&self, invocation: u64) -> Option<&dyn Shadow> {
+        if invocation == 0 || invocation != self.invocation.get() {
+            return None;
+        }
+        // SAFETY: The shadow heap is alive while it’s in the state.
+        self.shadow.get().map(|ptr| unsafe { &*ptr.as_ptr() })
+    }
+
+    /// Records and reports allocation which didn’t fit on the shadow heap.
+    fn fail(&self, shadow: &dyn Shadow, layout: Layout) {
+        if self.failure.get().is_some() {
+            return;
+        }
+        let size = self.size.get();
+        let used = size.saturating_sub(shadow.remaining());
+        let failure = HeapExhausted { layout, used, size };
+        self.failure.set(Some(failure));
+        // Don’t track allocations made while reporting.
+        let ptr = self.shadow.take();
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        std::eprintln!("solana-allocator: {failure}\n{backtrace}");
+        self.shadow.set(ptr);
+    }
+}
+
+/// Runs `f` with state of the current thread.  Returns `None` if the state is
+/// not accessible.
+fn with_state<R>(f: impl FnOnce(&State) -> Option<R>) -> Option<R> {
+    STATE.try_with(f).ok().flatten()
+}
+
+/// Returns layout of the system allocation holding a block of given layout
+/// and offset of the block within it.
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(core::mem::align_of::<Prefix>());
+    let offset = core::mem::size_of::<Prefix>().next_multiple_of(align);
+    let size = offset.checked_add(layout.size())?;
+    Some((Layout::from_size_align(size, align).ok()?, offset))
+}
+
+/// Returns prefix of given block.
+///
+/// # Safety
+///
+/// `ptr` must be a block allocated by [`HostAllocator`].
+unsafe fn prefix<'a>(ptr: *mut u8) -> &'a mut Prefix {
+    // SAFETY: Prefix is stored right before the block and is aligned.
+    unsafe { &mut *ptr.cast::<Prefix>().sub(1) }
+}
+
+/// Allocates a block on the current invocation’s shadow heap.
+fn track(layout: Layout) -> Prefix {
+    with_state(|state| {
+        let invocation = state.invocation.get();
+        let shadow = state.shadow(invocation)?;
+        let ptr = shadow.alloc(layout);
+        if ptr.is_null() {
+            state.fail(shadow, layout);
+            return None;
+        }
+        Some(Prefix { invocation, shadow: ptr })
+    })
+    .unwrap_or(Prefix { invocation: 0, shadow: core::ptr::null_mut() })
+}
+
+impl<G> HostAllocator<G> {
+    /// Allocates a block with the system allocator and on the shadow heap.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::alloc`].
+    unsafe fn allocate(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let Some((outer, offset)) = outer_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+        // SAFETY: outer has non-zero size since it includes the prefix.
+        let ptr = unsafe {
+            if zeroed {
+                System.alloc_zeroed(outer)
+            } else {
+                System.alloc(outer)
+            }
+        };
+        if ptr.is_null() {
+            return ptr;
+        }
+        // SAFETY: offset is within the allocated region.
+        let ptr = unsafe { ptr.add(offset) };
+        // SAFETY: Prefix fits in front of the block.
+        unsafe { ptr.cast::<Prefix>().sub(1).write(track(layout)) };
+        ptr
+    }
+}
+
+// SAFETY: Memory is managed by the system allocator.  Shadow heaps are used
+// for accounting only.
+unsafe impl<G> GlobalAlloc for HostAllocator<G> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { self.allocate(layout, false) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: Caller upholds the requirements.
+        unsafe { self.allocate(layout, true) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // The layout was valid when the block was allocated.
+        let (outer, offset) = outer_layout(layout).unwrap();
+        // SAFETY: ptr has been allocated by us.
+        let prefix = unsafe { prefix(ptr) };
+        with_state(|state| {
+            let shadow = state.shadow(prefix.invocation)?;
+            // SAFETY: The block has been allocated on the shadow heap with the
+            // same layout.
+            unsafe { shadow.dealloc(prefix.shadow, layout) };
+            Some(())
+        });
+        // SAFETY: ptr has been allocated by us with outer layout.
+        unsafe { System.dealloc(ptr.sub(offset), outer) }
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // The layout was valid when the block was allocated.
+        let (outer, offset) = outer_layout(layout).unwrap();
+        let Some(new_layout) =
+            Layout::from_size_align(new_size, layout.align()).ok()
+        else {
+            return core::ptr::null_mut();
+        };
+        let Some((new_outer, _)) = outer_layout(new_layout) else {
+            return core::ptr::null_mut();
+        };
+        // SAFETY: ptr has been allocated by us with outer layout.
+        let new_ptr =
+            unsafe { System.realloc(ptr.sub(offset), outer, new_outer.size()) };
+        if new_ptr.is_null() {
+            return new_ptr;
+        }
+        // SAFETY: Alignment didn’t change so neither did the offset.
+        let ptr = unsafe { new_ptr.add(offset) };
+        // SAFETY: ptr has been allocated by us.
+        let prefix = unsafe { prefix(ptr) };
+        with_state(|state| {
+            let shadow = state.shadow(prefix.invocation)?;
+            // SAFETY: The block has been allocated on the shadow heap with the
+            // same layout.
+            let new =
+                unsafe { shadow.realloc(prefix.shadow, layout, new_size) };
+            if new.is_null() {
+                state.fail(shadow, new_layout);
+                // The block is no longer tracked.  Free it so that the shadow
+                // heap doesn’t hold memory the program doesn’t use any more.
+                // SAFETY: Failed realloc leaves the old block allocated.
+                unsafe { shadow.dealloc(prefix.shadow, layout) };
+                prefix.invocation = 0;
+            } else {
+                prefix.shadow = new;
+            }
+            Some(())
+        });
+        ptr
+    }
+}
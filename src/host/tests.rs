@@ -0,0 +1,128 @@
+use super::*;
+
+/// Allocates a block with `allocator`.
+fn alloc(allocator: &HostAllocator, size: usize) -> *mut u8 {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(0, ptr as usize % 8);
+    // The block is usable regardless of the shadow heap.
+    unsafe { ptr.write_bytes(0xAA, size) };
+    ptr
+}
+
+/// Frees a block allocated with [`alloc`].
+fn dealloc(allocator: &HostAllocator, ptr: *mut u8, size: usize) {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    unsafe { allocator.dealloc(ptr, layout) }
+}
+
+#[test]
+fn test_untracked() {
+    let allocator = HostAllocator::new(64);
+    // Outside of an invocation, there are no limits.
+    let ptr = alloc(&allocator, 1024);
+    dealloc(&allocator, ptr, 1024);
+}
+
+#[test]
+fn test_invoke() {
+    let allocator = HostAllocator::new(4096);
+    let result = allocator.invoke(|| {
+        // Freed memory is reclaimed just like on chain.
+        for _ in 0..10 {
+            let ptr = alloc(&allocator, 2048);
+            dealloc(&allocator, ptr, 2048);
+        }
+        42
+    });
+    assert_eq!(Ok(42), result);
+
+    // Each invocation starts with a fresh heap.
+    for _ in 0..2 {
+        let result = allocator.invoke(|| alloc(&allocator, 2048));
+        let ptr = result.unwrap();
+        // Blocks outliving their invocation can be freed.
+        dealloc(&allocator, ptr, 2048);
+    }
+}
+
+#[test]
+fn test_heap_exhausted() {
+    let allocator = HostAllocator::new(4096);
+    let err = allocator
+        .invoke(|| {
+            let first = alloc(&allocator, 2048);
+            // The allocation succeeds even though it wouldn’t on chain.
+            let second = alloc(&allocator, 4096);
+            dealloc(&allocator, second, 4096);
+            dealloc(&allocator, first, 2048);
+        })
+        .unwrap_err();
+    assert_eq!(Layout::from_size_align(4096, 8).unwrap(), err.layout());
+    assert_eq!(4096, err.heap_size());
+    assert!(err.used() > 2048);
+}
+
+#[test]
+fn test_realloc() {
+    let allocator = HostAllocator::new(4096);
+    let err = allocator
+        .invoke(|| {
+            let mut ptr = alloc(&allocator, 16);
+            let mut size = 16;
+            for new_size in [64, 256, 8192, 16] {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                ptr = unsafe { allocator.realloc(ptr, layout, new_size) };
+                assert_eq!(0xAA, unsafe { *ptr });
+                size = new_size;
+            }
+            dealloc(&allocator, ptr, size);
+        })
+        .unwrap_err();
+    assert_eq!(8192, err.layout().size());
+}
+
+#[test]
+fn test_realloc_failed() {
+    /// Returns remaining space on the current invocation’s shadow heap.
+    fn remaining() -> usize {
+        STATE.with(|state| {
+            state.shadow(state.invocation.get()).unwrap().remaining()
+        })
+    }
+
+    let allocator = HostAllocator::new(4096);
+    let err = allocator
+        .invoke(|| {
+            // With `checked-free`, first allocation reserves memory for
+            // records which is never freed.
+            dealloc(&allocator, alloc(&allocator, 8), 8);
+            let before = remaining();
+            let ptr = alloc(&allocator, 2048);
+            let layout = Layout::from_size_align(2048, 8).unwrap();
+            let ptr = unsafe { allocator.realloc(ptr, layout, 8192) };
+            // The block is no longer on the shadow heap.
+            assert_eq!(before, remaining());
+            dealloc(&allocator, ptr, 8192);
+            assert_eq!(before, remaining());
+        })
+        .unwrap_err();
+    assert_eq!(8192, err.layout().size());
+}
+
+#[test]
+fn test_nested() {
+    let allocator = HostAllocator::new(4096);
+    let result = allocator.invoke(|| {
+        let ptr = alloc(&allocator, 2048);
+        // Nested invocation has its own heap.
+        let inner = allocator.invoke(|| {
+            let ptr = alloc(&allocator, 2048);
+            dealloc(&allocator, ptr, 2048);
+        });
+        dealloc(&allocator, ptr, 2048);
+        inner
+    });
+    assert_eq!(Ok(Ok(())), result);
+}
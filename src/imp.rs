@@ -1,6 +1,6 @@
 // Rust doesn’t recognise ‘solana’ as a target_os unless building via cargo
 // build-sbf.  Silence the warning.
-#![cfg(any(test, target_os = "solana"))]
+#![cfg(any(test, feature = "testing", fixed_heap))]
 #![cfg_attr(not(target_os = "solana"), allow(unexpected_cfgs))]
 #![allow(private_bounds)]
 
@@ -16,9 +16,38 @@ extern crate alloc;
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::cell::Cell;
 
+#[cfg(feature = "reclaim")]
+mod block;
+#[cfg(feature = "size-classes")]
+mod classes;
+#[cfg(feature = "debug-heap")]
+mod debug;
+#[cfg(feature = "trace")]
+mod recorder;
+#[cfg(feature = "checked-free")]
+mod records;
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "reclaim")]
+use block::Block;
+#[cfg(feature = "size-classes")]
+use classes::SizeClasses;
+#[cfg(feature = "debug-heap")]
+use debug::{Canary, Redzones};
+#[cfg(feature = "trace")]
+use recorder::Recorder;
+#[cfg(feature = "checked-free")]
+use records::Records;
+
+use crate::cell::GlobalCell;
+use crate::heap::{AllocError, Heap};
+use crate::slot::Registry;
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+#[cfg(feature = "trace")]
+use crate::trace::Event;
+
 /// Custom bump allocator for on-chain operations.
 ///
 /// The default allocator is also a bump one, but grows from a fixed
@@ -33,45 +62,77 @@ mod tests;
 /// start of the heap and accessible through [`Self::global`] method.  This is
 /// meant to work-around Solana’s lack of support for mutable statics.
 pub struct BumpAllocator<G> {
-    #[cfg(test)]
-    ptr: core::ptr::NonNull<u8>,
-    #[cfg(test)]
-    layout: Layout,
-
+    heap: Heap,
     _ph: core::marker::PhantomData<G>,
 }
 
 
-/// Start address of the memory region used for program heap.
+/// Data stored by the [`BumpAllocator`] at the start of the heap.
 ///
-/// This is the same as `solana_sdk::entrypoint::HEAP_START_ADDRESS`.
-#[cfg(not(test))]
-const HEAP_START_ADDRESS: u64 = 0x3_0000_0000;
+/// With `debug-heap` feature, the fields are laid out in declaration order so
+/// that the canaries surround the global state.
+#[cfg_attr(feature = "debug-heap", repr(C))]
+struct Header<G> {
+    end_pos: Cell<*mut u8>,
+    /// End of the heap declared at run time or null if it hasn’t been.
+    heap_end: Cell<*mut u8>,
+    /// Global slots declared with `global_slot` macro.
+    slots: Registry,
+    /// Highest end position ever reached or null if nothing has been
+    /// allocated yet.  Memory above it has never been handed out and thus is
+    /// still zero-filled.
+    untouched: Cell<*mut u8>,
+    /// Most recently allocated block which hasn’t been released.
+    #[cfg(feature = "reclaim")]
+    last: Cell<*mut Block>,
+    /// Free lists of small blocks.
+    #[cfg(feature = "size-classes")]
+    classes: SizeClasses,
+    /// Live allocations used to check deallocations.
+    #[cfg(feature = "checked-free")]
+    records: Records,
+    /// Number of live allocations.  Used to verify rollbacks in debug builds
+    /// but kept in release builds too so that header layout doesn’t depend on
+    /// the build profile.
+    live: Cell<usize>,
+    /// Allocation statistics.
+    #[cfg(feature = "stats")]
+    stats: Cell<Stats>,
+    /// Recorder of allocator calls.
+    #[cfg(feature = "trace")]
+    trace: Recorder,
+    /// Live allocations with redzones around them.
+    #[cfg(feature = "debug-heap")]
+    redzones: Redzones,
+    /// Canary protecting allocator’s data from writes before global state.
+    #[cfg(feature = "debug-heap")]
+    canary: Canary,
+    global: GlobalCell<G>,
+    /// Canary catching writes past global state.
+    #[cfg(feature = "debug-heap")]
+    canary_end: Canary,
+}
 
-/// Minimal length of the heap memory region used for program heap.
-///
-/// The actual heap size may be larger if Compute Budget Program’s
-/// `RequestHeapFrame` instruction was used.
-///
-/// This is the same as `solana_sdk::entrypoint::HEAP_LENGTH`.
-#[cfg(not(test))]
-const HEAP_LENGTH: usize = 32 * 1024;
+// SAFETY: All fields are Zeroable; pointers are null when zeroed.
+unsafe impl<G: bytemuck::Zeroable> bytemuck::Zeroable for Header<G> {}
 
-/// Start address of the memory region where program input parameters are
-/// stored.
+/// Saved position of the [`BumpAllocator`]’s end of allocated memory.
 ///
-/// See <https://solana.com/docs/programs/faq#memory-map>.
-#[cfg(not(test))]
-const PROGRAM_INPUT_ADDRESS: u64 = 0x4_0000_0000;
-
-
-/// Data stored by the [`BumpAllocator`] at the start of the heap.
-struct Header<G> {
-    end_pos: Cell<*mut u8>,
-    global: G,
+/// Created by [`BumpAllocator::checkpoint`] and consumed by
+/// [`BumpAllocator::rollback`] which frees everything allocated after the
+/// checkpoint was taken.
+#[must_use]
+pub struct Checkpoint {
+    end_pos: *mut u8,
+    slots: *mut u8,
+    #[cfg(feature = "reclaim")]
+    last: *mut Block,
+    #[cfg(feature = "checked-free")]
+    seq: u32,
+    live: usize,
 }
 
-#[cfg(not(test))]
+#[cfg(all(fixed_heap, not(test)))]
 impl<G> BumpAllocator<G> {
     /// Creates a new global allocator.
     ///
@@ -84,32 +145,68 @@ impl<G> BumpAllocator<G> {
     /// allocator is present leads to undefined behaviour since the allocator
     /// needs to take ownership of the heap provided by Solana runtime.
     pub const unsafe fn new() -> Self {
-        Self { _ph: core::marker::PhantomData }
+        // SAFETY: Caller upholds the same requirements.
+        unsafe { Self::with_heap_size(0) }
     }
 
-    /// Returns start of the heap.
-    const fn heap_start(&self) -> *mut u8 { HEAP_START_ADDRESS as *mut u8 }
-
-    /// Returns safe end of the heap, i.e. end of a region that is guaranteed to
-    /// be valid heap.
+    /// Creates a new global allocator for heap of given size.
+    ///
+    /// Allocations which don’t fit in the heap of declared `size` fail
+    /// (returning null pointer from [`GlobalAlloc::alloc`] and error from
+    /// [`Self::try_alloc`]) rather than causing an access violation.  If
+    /// `size` is zero, the heap size is unknown which is the same as using
+    /// [`Self::new`].
+    ///
+    /// The size can also be declared at run time with
+    /// [`Self::set_heap_size`].  Declaring size larger than the actual heap
+    /// results in an access violation when the memory runs out just like when
+    /// the size isn’t declared.  Size reaching into the program input region
+    /// is capped at its start.
+    ///
+    /// # Safety
     ///
-    /// Since we don’t know the actual Solana heap size, this is limited to just
-    /// 32 KiB when running on Solana (which is guaranteed minimum heap size).
-    const fn heap_safe_end(&self) -> *mut u8 {
-        (HEAP_START_ADDRESS + HEAP_LENGTH as u64) as *mut u8
+    /// Same as for [`Self::new`].
+    pub const unsafe fn with_heap_size(size: usize) -> Self {
+        Self { heap: Heap::new(size), _ph: core::marker::PhantomData }
     }
-
-    /// Returns the address at which there’s definitely no heap.
-    const fn heap_limit(&self) -> *mut u8 { PROGRAM_INPUT_ADDRESS as *mut u8 }
 }
 
-#[cfg(test)]
-impl<G> BumpAllocator<G> {
-    fn heap_start(&self) -> *mut u8 { self.ptr.as_ptr() }
-    fn heap_safe_end(&self) -> *mut u8 {
-        self.heap_start().wrapping_add(self.layout.size())
+#[cfg(any(not(fixed_heap), test))]
+impl<G: bytemuck::Zeroable> BumpAllocator<G> {
+    /// Creates a new allocator operating on a zero-initialised heap of given
+    /// size allocated with the global allocator.
+    ///
+    /// The heap has the same layout as on Solana, i.e. it starts with
+    /// allocator’s header and global state.  This makes it possible to check
+    /// in unit tests whether code fits in a heap of given size, e.g. with
+    /// allocator-api2 collections:
+    ///
+    /// ```
+    /// # #[cfg(feature = "testing")] {
+    /// use allocator_api2::vec::Vec;
+    ///
+    /// let allocator = solana_allocator::BumpAllocator::<()>::with_capacity(
+    ///     32 * 1024,
+    /// );
+    /// let mut vec = Vec::<u8, _>::new_in(&allocator);
+    /// assert!(vec.try_reserve(16 * 1024).is_ok());
+    /// assert!(vec.try_reserve(32 * 1024).is_err());
+    /// # }
+    /// ```
+    ///
+    /// Available when not building for Solana with `testing` Cargo feature.
+    /// Panics if the global state doesn’t fit in the heap.
+    pub fn with_capacity(size: usize) -> Self {
+        let this =
+            Self { heap: Heap::new(size), _ph: core::marker::PhantomData };
+        this.header();
+        this
     }
-    fn heap_limit(&self) -> *mut u8 { self.heap_safe_end() }
+
+    /// Returns start of the heap.  Used to translate addresses when replaying
+    /// traces.
+    #[cfg(feature = "trace")]
+    pub(crate) fn heap_start(&self) -> *mut u8 { self.heap.start() }
 }
 
 impl<G: bytemuck::Zeroable> BumpAllocator<G> {
@@ -118,34 +215,12 @@ impl<G: bytemuck::Zeroable> BumpAllocator<G> {
     ///
     /// The header includes address of the start of the available free memory
     /// and global state `G` reserved for the users of this allocator.
-    fn header(&self) -> &Header<G> {
-        // In release build on Solana, all of those numbers are known at compile
-        // time so all this maths should be compiled out.
-        let ptr = crate::ptr::align(
-            self.heap_start(),
-            core::mem::align_of::<Header<G>>(),
-        );
-        // Make sure that the header does not go past the safe portion of the
-        // heap (i.e. portion we are guaranteed to be accessible).
-        let end = ptr.wrapping_add(core::mem::size_of::<Header<G>>());
-        assert!(end <= self.heap_safe_end(), "Global state too large");
-        // SAFETY: 1. `ptr` is properly aligned and points to region within heap
-        // owned by us.  2. The heap has been zero-initialised and Header<G> is
-        // Zeroable.
-        unsafe { &*ptr.cast() }
-    }
+    fn header(&self) -> &Header<G> { self.heap.header() }
 
     /// Checks whether given slice falls within available heap space and updates
     /// end position address if it does.
     ///
-    /// Outside of unit tests, the check is done by writing zero byte to the
-    /// last byte of the slice which will cause UB if it fails beyond available
-    /// heap space.
-    ///
-    /// When run as Solana contract that UB is segfault.  If `poke` Cargo
-    /// feature is enabled, the segfault happens when trying to allocate; by
-    /// default it’s deferred to the moment region past the heap is accessed by
-    /// the client (a bit like over-committing works in Linux).
+    /// See [`Heap::reserve`] for description of how the check is done.
     ///
     /// If check passes, returns `ptr` aligned to `layout.align()`.  Otherwise
     /// returns a NULL pointer.
@@ -155,21 +230,271 @@ impl<G: bytemuck::Zeroable> BumpAllocator<G> {
         ptr: *mut u8,
         layout: Layout,
     ) -> *mut u8 {
+        #[cfg(feature = "stats")]
+        let unaligned = ptr as usize;
         let ptr = crate::ptr::align(ptr, layout.align());
-        (ptr as usize)
-            .checked_add(layout.size())
-            .map(|addr| crate::ptr::with_addr(ptr, addr))
-            .filter(|&end| end <= self.heap_limit())
-            .map_or(core::ptr::null_mut(), |end| {
-                if !cfg!(test) && cfg!(feature = "poke") {
-                    // SAFETY: This is unsound but it will only execute on
-                    // Solana where accessing memory beyond heap results in
-                    // segfault which is what we want.
-                    let _ = unsafe { end.sub(1).read_volatile() };
-                }
+        self.heap.reserve(ptr, layout.size(), header.heap_end.get()).map_or(
+            core::ptr::null_mut(),
+            |end| {
                 header.end_pos.set(end);
+                if end > header.untouched.get() {
+                    header.untouched.set(end);
+                }
+                #[cfg(feature = "stats")]
+                Stats::record_use(
+                    &header.stats,
+                    end as usize - crate::ptr::end_addr_of_val(header),
+                    ptr as usize - unaligned,
+                );
                 ptr
-            })
+            },
+        )
+    }
+
+    /// Allocates a new block with metadata in front of it starting at `ptr`.
+    ///
+    /// Returns pointer to the block’s data or NULL if there’s no space.
+    #[cfg(feature = "reclaim")]
+    fn alloc_block(
+        &self,
+        header: &Header<G>,
+        ptr: *mut u8,
+        layout: Layout,
+    ) -> *mut u8 {
+        // Reserve space for the metadata.  update_end_pos aligns the pointer
+        // to layout.align() so that the metadata is directly in front of the
+        // data.  If the alignment is greater than Block::ALIGN, the padding
+        // ends up in front of the metadata.
+        let block = crate::ptr::align(ptr, Block::ALIGN);
+        let data = self.update_end_pos(
+            header,
+            block.wrapping_add(Block::SIZE),
+            layout,
+        );
+        if !data.is_null() {
+            #[cfg(feature = "stats")]
+            Stats::record_use(&header.stats, 0, block as usize - ptr as usize);
+            // SAFETY: data is aligned to at least Block::ALIGN and we’ve
+            // reserved space for the metadata in front of it.
+            let block = unsafe { Block::init(data, ptr, header.last.get()) };
+            header.last.set(core::ptr::from_ref(block).cast_mut());
+        }
+        data
+    }
+
+    /// Releases the last block and all directly preceding freed blocks.
+    ///
+    /// Moves end position to the start of the earliest released block.
+    #[cfg(feature = "reclaim")]
+    fn release(&self, header: &Header<G>, block: &Block) {
+        let mut block = block;
+        let last = loop {
+            header.end_pos.set(block.start());
+            // SAFETY: Previous block is either null or a valid block.
+            match unsafe { block.prev().as_ref() } {
+                Some(prev) if prev.is_freed() => block = prev,
+                _ => break block.prev(),
+            }
+        };
+        header.last.set(last);
+    }
+
+    /// Returns layout of the block actually reserved for an allocation with
+    /// given layout.
+    ///
+    /// With `size-classes` feature, small allocations are rounded up to their
+    /// size class.  Otherwise, this returns the argument unchanged.
+    fn block_layout(layout: Layout) -> Layout {
+        #[cfg(feature = "size-classes")]
+        let layout = SizeClasses::layout(layout);
+        layout
+    }
+
+    /// Allocates a new block, copies data from the old one and frees it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation with layout `layout` and `new_layout`
+    /// must have non-zero size.
+    #[cfg(not(feature = "debug-heap"))]
+    unsafe fn move_block(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> *mut u8 {
+        // SAFETY: Caller guarantees new layout has non-zero size.
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            let size = layout.size().min(new_layout.size());
+            // SAFETY: The previously allocated block cannot overlap the newly
+            // allocated block.
+            unsafe { crate::ptr::memcpy(new_ptr, ptr, size) }
+            // SAFETY: Caller guarantees ptr is a live allocation.
+            unsafe { self.dealloc(ptr, layout) }
+        }
+        new_ptr
+    }
+
+    /// Returns address above which heap memory is still zero-filled.
+    ///
+    /// With `debug-heap` feature, freshly allocated memory is filled with
+    /// a pattern so no part of the heap is assumed to be zero-filled.
+    fn untouched(&self) -> *mut u8 {
+        let untouched = self.header().untouched.get();
+        #[cfg(feature = "debug-heap")]
+        let untouched = crate::ptr::with_addr(untouched, usize::MAX);
+        untouched
+    }
+
+    /// Zeroes portion of `[ptr, ptr + size)` region which lies below
+    /// `untouched` address.
+    ///
+    /// Memory above `untouched` has never been handed out and thus is already
+    /// zero-filled.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for writes.
+    unsafe fn zero_dirty(ptr: *mut u8, size: usize, untouched: *mut u8) {
+        let end = ptr.wrapping_add(size).min(untouched);
+        if ptr < end {
+            // SAFETY: [ptr, end) is part of the region caller guarantees is
+            // valid.
+            unsafe { ptr.write_bytes(0, end as usize - ptr as usize) };
+        }
+    }
+
+    /// Reallocates memory zeroing the newly added part of the block.
+    ///
+    /// This is like [`GlobalAlloc::realloc`] except that if `new_size` is
+    /// greater than `layout.size()`, bytes past the old size are zeroed.  Just
+    /// like [`GlobalAlloc::alloc_zeroed`], the allocator doesn’t zero memory
+    /// which has never been allocated since Solana provides zero-filled heap.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::realloc`].
+    pub unsafe fn realloc_zeroed(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let untouched = self.untouched();
+        // SAFETY: Caller upholds the same requirements.
+        let new_ptr = unsafe { self.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() && new_size > layout.size() {
+            let grown = new_ptr.wrapping_add(layout.size());
+            let size = new_size - layout.size();
+            // SAFETY: The grown part is within the allocated block.
+            unsafe { Self::zero_dirty(grown, size, untouched) };
+        }
+        new_ptr
+    }
+
+    /// Declares size of the heap at run time.
+    ///
+    /// Once the size is declared, allocations which don’t fit in the heap fail
+    /// rather than causing an access violation.  This overrides size passed to
+    /// [`Self::with_heap_size`].  Setting size to zero reverts to the size
+    /// declared at compile time (if any).
+    ///
+    /// The size includes space used by the global state and allocator’s
+    /// internal data stored at the start of the heap.  If declared size is
+    /// larger than the actual heap, running out of memory results in an access
+    /// violation just like when the size isn’t declared.  The size is capped
+    /// so that the heap never reaches into the program input region (or past
+    /// the buffer of an allocator created with `with_capacity`).
+    pub fn set_heap_size(&self, size: usize) {
+        self.header().heap_end.set(self.heap.heap_end(size));
+    }
+
+    /// Declares size of the heap based on the Instructions sysvar.
+    ///
+    /// `key` and `data` must be address and contents of the Instructions
+    /// sysvar account.  Looks for Compute Budget program’s `RequestHeapFrame`
+    /// instruction and declares the requested size (or the default 32 KiB if
+    /// there’s no such instruction) as with [`Self::set_heap_size`].
+    ///
+    /// Returns the declared size.  If `key` isn’t the address of the
+    /// Instructions sysvar or the data is malformed, returns `None` and
+    /// doesn’t change the heap size.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let sysvar = &accounts[0];
+    /// A.set_heap_size_from_sysvar(sysvar.key.as_ref(), &sysvar.data.borrow());
+    /// ```
+    pub fn set_heap_size_from_sysvar(
+        &self,
+        key: &[u8],
+        data: &[u8],
+    ) -> Option<usize> {
+        if key != crate::sysvar::INSTRUCTIONS_SYSVAR_ID {
+            return None;
+        }
+        let size = crate::sysvar::requested_heap_frame(data)?;
+        let size = usize::try_from(size).ok()?;
+        self.set_heap_size(size);
+        Some(size)
+    }
+
+    /// Returns number of bytes available for allocation at the end of the
+    /// heap or `None` if heap size isn’t known.
+    ///
+    /// Heap size is known if it has been declared either at compile time (see
+    /// [`Self::with_heap_size`]) or at run time (see [`Self::set_heap_size`]
+    /// and [`Self::set_heap_size_from_sysvar`]).  Note that because of
+    /// alignment requirements and (depending on enabled features) per-block
+    /// metadata, allocation of the returned size may still fail.
+    pub fn remaining(&self) -> Option<usize> {
+        let header = self.header();
+        let end = self.heap.known_end(header.heap_end.get())?;
+        let pos = match header.end_pos.get() {
+            ptr if ptr.is_null() => crate::ptr::end_addr_of_val(header),
+            ptr => ptr as usize,
+        };
+        Some((end as usize).saturating_sub(pos))
+    }
+
+    /// Returns allocation statistics.
+    ///
+    /// Available only if `stats` Cargo feature is enabled.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        let header = self.header();
+        let start = crate::ptr::end_addr_of_val(header);
+        let used = (header.end_pos.get() as usize).saturating_sub(start);
+        let reserved = start - self.heap.start() as usize;
+        Stats { used, reserved, ..header.stats.get() }
+    }
+
+    /// Resets peak heap usage reported by [`Self::stats`] to current usage.
+    ///
+    /// This allows measuring peak usage of a portion of the program.
+    /// Available only if `stats` Cargo feature is enabled.
+    #[cfg(feature = "stats")]
+    pub fn reset_peak(&self) {
+        let used = self.stats().used;
+        Stats::update(&self.header().stats, |stats| stats.peak = used);
+    }
+
+    /// Allocates memory as described by `layout`.
+    ///
+    /// This is like [`GlobalAlloc::alloc`] except that it returns an error if
+    /// there’s not enough memory rather than a null pointer.  Note that unless
+    /// the heap size has been declared (see [`Self::set_heap_size`] and
+    /// [`Self::with_heap_size`]), running out of memory on Solana results in
+    /// an access violation rather than an error.
+    pub fn try_alloc(
+        &self,
+        layout: Layout,
+    ) -> Result<core::ptr::NonNull<u8>, AllocError> {
+        // SAFETY: Our alloc handles zero-sized layouts.
+        let ptr = unsafe { self.alloc(layout) };
+        core::ptr::NonNull::new(ptr).ok_or(AllocError::new(layout))
     }
 
     /// Returns reference to global state `G` reserved on the heap.
@@ -180,36 +505,344 @@ impl<G: bytemuck::Zeroable> BumpAllocator<G> {
     ///
     /// Note that by default `G` is a unit type which means that there is no
     /// reserved global state.
-    pub fn global(&self) -> &G { &self.header().global }
-}
+    ///
+    /// Panics if the global state is borrowed by [`Self::with_global_mut`].
+    /// Once this method is called, the global state can no longer be borrowed
+    /// mutably.
+    #[track_caller]
+    pub fn global(&self) -> &G { self.header().global.get() }
 
-unsafe impl<G: bytemuck::Zeroable> GlobalAlloc for BumpAllocator<G> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// Calls `f` with exclusive reference to global state `G`.
+    ///
+    /// This is an alternative to [`Self::global`] which lets `G` be a plain
+    /// structure without any `Cell` wrappers.  Only one mutable borrow may
+    /// exist at a time and it can’t coexist with shared references returned by
+    /// [`Self::global`].  Since those are `'static`, once [`Self::global`] is
+    /// called the global state can no longer be borrowed mutably.  A program
+    /// should therefore use one of the two methods consistently.
+    /// [`custom_global`](`crate::custom_global`) macro with `mut` prefix
+    /// defines an accessor which uses this method and never calls
+    /// [`Self::global`].
+    ///
+    /// Panics if the global state is already borrowed (e.g. when called from
+    /// within `f`).  See [`Self::try_with_global_mut`] for non-panicking
+    /// version.
+    #[track_caller]
+    pub fn with_global_mut<R>(&self, f: impl FnOnce(&mut G) -> R) -> R {
+        self.try_with_global_mut(f).expect("global state already borrowed")
+    }
+
+    /// Calls `f` with exclusive reference to global state `G` or returns
+    /// `None` if the global state is already borrowed.
+    ///
+    /// See [`Self::with_global_mut`].
+    pub fn try_with_global_mut<R>(
+        &self,
+        f: impl FnOnce(&mut G) -> R,
+    ) -> Option<R> {
+        self.header().global.try_with_mut(f)
+    }
+
+    /// Checks integrity of the heap.
+    ///
+    /// Verifies canaries around the global state and redzones around all live
+    /// allocations.  Panics if any of them has been overwritten which
+    /// indicates an out-of-bounds write.  Blocks are also checked when they
+    /// are freed or resized.
+    ///
+    /// Available only if `debug-heap` Cargo feature is enabled.
+    #[cfg(feature = "debug-heap")]
+    #[track_caller]
+    pub fn verify_heap(&self) {
         let header = self.header();
+        let end = header.end_pos.get();
+        if end.is_null() {
+            return;
+        }
+        Self::check_header(header);
+        let start =
+            crate::ptr::with_addr(end, crate::ptr::end_addr_of_val(header));
+        header.redzones.verify(start, end);
+    }
+
+    /// Starts recording allocator calls.
+    ///
+    /// Reserves a buffer of `capacity` bytes at the end of the heap (or
+    /// slightly more if `capacity` is very small) and records every `alloc`,
+    /// `dealloc` and `realloc` call made from now on.  The buffer is never
+    /// freed so recording should start at the beginning of the instruction.
+    /// See [`crate::trace`] for description of the format.
+    ///
+    /// If recording is already in progress, it’s stopped first and its buffer
+    /// is abandoned.
+    ///
+    /// Available only if `trace` Cargo feature is enabled.
+    #[cfg(feature = "trace")]
+    pub fn start_trace(
+        &self,
+        capacity: usize,
+        output: crate::trace::Output,
+    ) -> Result<(), AllocError> {
+        let capacity = capacity.max(Recorder::MIN_CAPACITY);
+        let layout = Layout::array::<u8>(capacity)
+            .map_err(|_| AllocError::new(Layout::new::<u8>()))?;
+        let header = self.header();
+        let buf = self.alloc_at_end(header, layout);
+        if buf.is_null() {
+            return Err(AllocError::new(layout));
+        }
+        // SAFETY: We’ve just allocated the buffer and it’ll never be freed.
+        unsafe { header.trace.start(buf, capacity, output) };
+        Ok(())
+    }
+
+    /// Stops recording allocator calls and returns the recorded trace.
+    ///
+    /// With [`Output::Log`](`crate::trace::Output::Log`), flushes the rest of
+    /// the trace to logs and returns an empty slice.
+    ///
+    /// Available only if `trace` Cargo feature is enabled.
+    #[cfg(feature = "trace")]
+    pub fn stop_trace(&self) -> &[u8] { self.header().trace.stop() }
+
+    /// Returns address of an allocation as recorded in the trace, i.e. offset
+    /// from the start of the heap or zero for null pointer.
+    #[cfg(feature = "trace")]
+    fn trace_addr(&self, ptr: *mut u8) -> usize {
+        if ptr.is_null() {
+            0
+        } else {
+            ptr as usize - self.heap.start() as usize
+        }
+    }
+
+    /// Returns registry of global slots.  Used by the macros.
+    #[doc(hidden)]
+    pub fn registry(&self) -> &Registry { &self.header().slots }
+
+    /// Records current end of allocated memory.
+    ///
+    /// The checkpoint can later be passed to [`Self::rollback`] to free all
+    /// memory allocated after the checkpoint was created regardless of the
+    /// order in which the objects were freed (or whether they were freed at
+    /// all).
+    pub fn checkpoint(&self) -> Checkpoint {
+        let header = self.header();
+        Checkpoint {
+            end_pos: header.end_pos.get(),
+            slots: header.slots.head(),
+            #[cfg(feature = "reclaim")]
+            last: header.last.get(),
+            #[cfg(feature = "checked-free")]
+            seq: header.records.seq(),
+            live: header.live.get(),
+        }
+    }
+
+    /// Frees all memory allocated since given checkpoint has been created.
+    ///
+    /// If some of the memory allocated before the checkpoint has been freed in
+    /// the meantime, the end position is not moved forward.
+    ///
+    /// In debug builds, panics if there are more live allocations than when
+    /// the checkpoint was created.  This is a best-effort check which catches
+    /// objects allocated after the checkpoint and not freed; it won’t catch
+    /// cases where such an object remains alive while an object allocated
+    /// before the checkpoint is freed.
+    ///
+    /// # Safety
+    ///
+    /// Caller must guarantee that none of the objects allocated after the
+    /// checkpoint has been created are used after this call.  Furthermore,
+    /// none of objects allocated before the checkpoint may have been resized
+    /// (through `realloc`) after the checkpoint.
+    ///
+    /// Global slots (see [`crate::slot`]) first accessed after the checkpoint
+    /// are reset and references to them must not be used after this call.
+    /// With `checked-free` Cargo feature, freeing objects allocated after the
+    /// checkpoint results in a panic.  With `trace` Cargo feature, recording
+    /// started after the checkpoint stops and trace returned by
+    /// [`Self::stop_trace`] must not be used after this call.
+    pub unsafe fn rollback(&self, checkpoint: Checkpoint) {
+        let header = self.header();
+        // Forget global slots registered after the checkpoint since their
+        // memory is freed.
+        // SAFETY: checkpoint.slots has been returned by header.slots.head().
+        let slots = unsafe { header.slots.truncate(checkpoint.slots) };
+        header.live.set(header.live.get() - slots);
+        #[cfg(debug_assertions)]
+        {
+            let live = header.live.get();
+            assert!(
+                live <= checkpoint.live,
+                "{} object(s) allocated after checkpoint are still alive",
+                live - checkpoint.live,
+            );
+        }
+        if checkpoint.end_pos < header.end_pos.get() {
+            header.end_pos.set(checkpoint.end_pos);
+            #[cfg(feature = "reclaim")]
+            {
+                header.last.set(checkpoint.last);
+                // The last block might have been freed after the checkpoint
+                // was created.
+                // SAFETY: Blocks allocated before the checkpoint are still
+                // on the list.
+                if let Some(block) = unsafe { checkpoint.last.as_ref() } {
+                    if block.is_freed() {
+                        self.release(header, block);
+                    }
+                }
+            }
+            #[cfg(feature = "size-classes")]
+            header.classes.forget_above(header.end_pos.get());
+            #[cfg(feature = "debug-heap")]
+            header.redzones.forget_above(header.end_pos.get());
+            #[cfg(feature = "trace")]
+            header.trace.forget_above(header.end_pos.get());
+        }
+        // Allocations made after the checkpoint mustn’t be freed any more.
+        #[cfg(feature = "checked-free")]
+        header.records.rollback(checkpoint.seq, header.end_pos.get());
+    }
+
+    /// Executes `f` and frees all memory it has allocated.
+    ///
+    /// This is a convenience wrapper around [`Self::checkpoint`] and
+    /// [`Self::rollback`].  It’s useful in loops where each iteration creates
+    /// temporary objects which are freed in order the bump allocator cannot
+    /// reclaim.
+    ///
+    /// # Safety
+    ///
+    /// Result of `f` must not reference any memory allocated by `f`.  See
+    /// [`Self::rollback`] for details.
+    pub unsafe fn with_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let checkpoint = self.checkpoint();
+        let result = f();
+        // SAFETY: Caller guarantees result doesn’t reference memory allocated
+        // in the scope.
+        unsafe { self.rollback(checkpoint) };
+        result
+    }
+}
+
+impl<G: bytemuck::Zeroable> BumpAllocator<G> {
+    /// Allocates a block at the end position.
+    ///
+    /// Unlike [`Self::bump_alloc`], doesn’t use size-class caches nor count
+    /// the block as an allocation.
+    fn alloc_at_end(&self, header: &Header<G>, layout: Layout) -> *mut u8 {
         let mut ptr = header.end_pos.get();
         if ptr.is_null() {
             // On first call, end_pos is null.  Start allocating past the
             // header.
             ptr = crate::ptr::with_addr(
-                self.heap_start(),
+                self.heap.start(),
                 crate::ptr::end_addr_of_val(header),
             );
+            #[cfg(feature = "debug-heap")]
+            {
+                header.canary.set();
+                header.canary_end.set();
+            }
         };
-        self.update_end_pos(header, ptr, layout)
+        #[cfg(feature = "reclaim")]
+        let ptr = self.alloc_block(header, ptr, layout);
+        #[cfg(not(feature = "reclaim"))]
+        let ptr = self.update_end_pos(header, ptr, layout);
+        ptr
     }
 
-    /// Deallocates specified object.
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Allocates a block directly on the heap.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::alloc`].
+    unsafe fn bump_alloc(&self, layout: Layout) -> *mut u8 {
         let header = self.header();
+        let layout = Self::block_layout(layout);
+        #[cfg(feature = "size-classes")]
+        {
+            let ptr = header.classes.pop(layout);
+            if !ptr.is_null() {
+                header.live.set(header.live.get() + 1);
+                #[cfg(feature = "stats")]
+                Stats::update(&header.stats, |stats| stats.allocs += 1);
+                return ptr;
+            }
+        }
+        let ptr = self.alloc_at_end(header, layout);
+        if !ptr.is_null() {
+            header.live.set(header.live.get() + 1);
+            #[cfg(feature = "stats")]
+            Stats::update(&header.stats, |stats| stats.allocs += 1);
+        }
+        ptr
+    }
+
+    /// Deallocates a block allocated with [`Self::bump_alloc`].
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::dealloc`].
+    unsafe fn bump_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = self.header();
+        let layout = Self::block_layout(layout);
+        header.live.set(header.live.get() - 1);
         // If this is the last allocation, free it.  Otherwise this is bump
-        // allocator and we leak memory.
-        if ptr.wrapping_add(layout.size()) == header.end_pos.get() {
+        // allocator and we leak memory (unless `size-classes` or `reclaim`
+        // feature is enabled).
+        let is_last = ptr.wrapping_add(layout.size()) == header.end_pos.get();
+        #[cfg(feature = "stats")]
+        Stats::update(&header.stats, |stats| stats.deallocs += 1);
+        // With `size-classes`, small blocks are put on a free list to be
+        // reused by subsequent allocations of the same size class.
+        #[cfg(feature = "size-classes")]
+        // SAFETY: Caller guarantees ptr is a live allocation and we’ve
+        // allocated it with the same rounded layout.
+        if !is_last && unsafe { header.classes.push(ptr, layout) } {
+            #[cfg(feature = "stats")]
+            Stats::update(&header.stats, |stats| stats.reclaimed += 1);
+            return;
+        }
+        // With `reclaim`, the block is marked as freed and released once all
+        // blocks after it are freed.
+        #[cfg(feature = "reclaim")]
+        {
+            // Memory of blocks freed out of order isn’t leaked since it’s
+            // released once blocks after them are freed.
+            #[cfg(feature = "stats")]
+            Stats::update(&header.stats, |stats| stats.reclaimed += 1);
+            // SAFETY: Caller guarantees ptr is a live allocation.
+            let block = unsafe { Block::of(ptr) };
+            if is_last {
+                self.release(header, block);
+            } else {
+                block.set_freed();
+            }
+        }
+        #[cfg(not(feature = "reclaim"))]
+        if is_last {
             header.end_pos.set(ptr);
         }
+        #[cfg(all(feature = "stats", not(feature = "reclaim")))]
+        Stats::update(&header.stats, |stats| {
+            if is_last {
+                stats.reclaimed += 1;
+            } else {
+                stats.leaked += 1;
+            }
+        });
     }
 
-    /// Reallocate an object.
-    unsafe fn realloc(
+    /// Resizes a block allocated with [`Self::bump_alloc`].
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::realloc`].
+    #[cfg(not(feature = "debug-heap"))]
+    unsafe fn bump_realloc(
         &self,
         ptr: *mut u8,
         layout: Layout,
@@ -220,31 +853,330 @@ unsafe impl<G: bytemuck::Zeroable> GlobalAlloc for BumpAllocator<G> {
             Layout::from_size_align_unchecked(new_size, layout.align())
         };
         let header = self.header();
-        let tail = header.end_pos.get();
-        if ptr.wrapping_add(layout.size()) == tail {
+        #[cfg(feature = "stats")]
+        Stats::update(&header.stats, |stats| stats.reallocs += 1);
+        let old_block = Self::block_layout(layout);
+        let new_block = Self::block_layout(new_layout);
+        // With `size-classes`, a large block cannot be turned into a cached
+        // one since it may not be aligned properly.
+        #[cfg(feature = "size-classes")]
+        let must_move = SizeClasses::is_cached(new_block) &&
+            !SizeClasses::is_cached(old_block);
+        #[cfg(not(feature = "size-classes"))]
+        let must_move = false;
+        if old_block == new_block {
+            // If the block doesn’t change, there’s nothing to do.
+            ptr
+        } else if must_move {
+            // SAFETY: Caller guarantees arguments are valid.
+            unsafe { self.move_block(ptr, layout, new_layout) }
+        } else if ptr.wrapping_add(old_block.size()) == header.end_pos.get() {
             // If this is the last allocation, resize.
-            self.update_end_pos(header, ptr, new_layout)
-        } else if new_size <= layout.size() {
+            self.update_end_pos(header, ptr, new_block)
+        } else if new_block.size() <= old_block.size() {
             // If user wants to shrink size, do nothing.  We’re leaking memory
             // here but we’re bump allocator so that’s what we do.
             ptr
         } else {
             // Otherwise, we need to make a new allocation and copy.
-            let new_ptr = self.update_end_pos(header, tail, new_layout);
-            if !new_ptr.is_null() {
-                // SAFETY: The previously allocated block cannot overlap the
-                // newly allocated block.  Note that layout.size() < new_size.
-                unsafe { crate::ptr::memcpy(new_ptr, ptr, layout.size()) }
+            // SAFETY: Caller guarantees arguments are valid.
+            unsafe { self.move_block(ptr, layout, new_layout) }
+        }
+    }
+
+    /// Allocates a block with redzones around it.
+    ///
+    /// Data of the block is filled with a pattern to catch uses of
+    /// uninitialised memory.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::alloc`].
+    #[cfg(feature = "debug-heap")]
+    unsafe fn debug_alloc(&self, layout: Layout) -> *mut u8 {
+        let header = self.header();
+        let Some(block) = Redzones::layout(layout) else {
+            return core::ptr::null_mut();
+        };
+        // SAFETY: Caller upholds the same requirements.
+        let ptr = unsafe { self.bump_alloc(block) };
+        if ptr.is_null() {
+            return ptr;
+        }
+        // SAFETY: We’ve just reserved the block.
+        unsafe { header.redzones.init(ptr, layout) }
+    }
+
+    /// Deallocates a block allocated with [`Self::debug_alloc`].
+    ///
+    /// Panics if the header’s canaries or block’s redzones have been
+    /// overwritten.  Data of the block is filled with a pattern to catch uses
+    /// after free.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::dealloc`].
+    #[cfg(feature = "debug-heap")]
+    #[track_caller]
+    unsafe fn debug_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = self.header();
+        Self::check_header(header);
+        // SAFETY: Caller guarantees ptr is a live allocation.
+        let block = unsafe { header.redzones.release(ptr, layout) };
+        // The layout has been checked when the block was allocated.
+        let layout = Redzones::layout(layout).unwrap();
+        // SAFETY: We’ve reserved the block with this layout.
+        unsafe { self.bump_dealloc(block, layout) }
+    }
+
+    /// Resizes a block allocated with [`Self::debug_alloc`].
+    ///
+    /// Unless the size doesn’t change, the data is always moved to a new block
+    /// so that stale pointers to the old block point at freed memory.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`GlobalAlloc::realloc`].
+    #[cfg(feature = "debug-heap")]
+    #[track_caller]
+    unsafe fn debug_realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let header = self.header();
+        Self::check_header(header);
+        // SAFETY: Caller guarantees ptr is a live allocation.
+        unsafe { header.redzones.check(ptr, layout.size()) };
+        #[cfg(feature = "stats")]
+        Stats::update(&header.stats, |stats| stats.reallocs += 1);
+        if new_size == layout.size() {
+            return ptr;
+        }
+        // SAFETY: Caller guarantees new layout is valid.
+        let new_layout = unsafe {
+            Layout::from_size_align_unchecked(new_size, layout.align())
+        };
+        // SAFETY: Caller guarantees new layout is valid.
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            let size = layout.size().min(new_size);
+            // SAFETY: Both blocks are live and don’t overlap.
+            unsafe {
+                crate::ptr::memcpy(new_ptr, ptr, size);
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+
+    /// Panics if canaries in the header have been overwritten.
+    ///
+    /// Canaries are initialised on first allocation so they aren’t checked if
+    /// nothing has been allocated.
+    #[cfg(feature = "debug-heap")]
+    #[track_caller]
+    fn check_header(header: &Header<G>) {
+        assert!(
+            header.end_pos.get().is_null() ||
+                (header.canary.is_intact() && header.canary_end.is_intact()),
+            "heap corruption: allocator header overwritten",
+        );
+    }
+
+    /// Makes sure there’s space in the records for another allocation.
+    /// Returns `false` if there’s no memory for it.
+    #[cfg(feature = "checked-free")]
+    fn reserve_record(&self) -> bool {
+        let header = self.header();
+        if header.records.is_full() {
+            let chunk = self.alloc_at_end(header, Records::CHUNK);
+            if chunk.is_null() {
+                return false;
             }
-            new_ptr
+            // SAFETY: We’ve just allocated the chunk and it’ll never be freed.
+            unsafe { header.records.add_chunk(chunk) };
         }
+        true
+    }
+
+    /// Checks that `ptr` is a live allocation with given `layout` and panics
+    /// if it isn’t.  If `remove` is true, removes the allocation from the
+    /// records.
+    #[cfg(feature = "checked-free")]
+    #[track_caller]
+    fn check_free(&self, ptr: *mut u8, layout: Layout, remove: bool) {
+        let header = self.header();
+        let start = crate::ptr::end_addr_of_val(header);
+        let in_heap = start <= ptr as usize && ptr <= header.end_pos.get();
+        assert!(
+            in_heap,
+            "invalid free of {ptr:?} with {layout:?}: not a heap allocation",
+        );
+        let entry = if remove {
+            header.records.remove(ptr)
+        } else {
+            header.records.get(ptr)
+        };
+        let Some(entry) = entry else {
+            panic!(
+                "invalid free of {ptr:?} with {layout:?}: not a live \
+                 allocation (double free?)"
+            );
+        };
+        assert!(
+            entry.size() == records::saturate(layout.size()),
+            "invalid free of {ptr:?} with {layout:?}: allocated with size {}",
+            entry.size(),
+        );
+        assert!(
+            ptr as usize & (layout.align() - 1) == 0,
+            "invalid free of {ptr:?} with {layout:?}: misaligned pointer",
+        );
     }
 }
 
-#[cfg(test)]
-impl<G> core::ops::Drop for BumpAllocator<G> {
-    fn drop(&mut self) {
-        // SAFETY: ptr and layout are the same as when we’ve allocated.
-        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+unsafe impl<G: bytemuck::Zeroable> GlobalAlloc for BumpAllocator<G> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Zero-size blocks may share their address with the next block so
+        // they aren’t recorded.
+        #[cfg(feature = "checked-free")]
+        let checkpoint = self.checkpoint();
+        #[cfg(feature = "checked-free")]
+        if layout.size() != 0 && !self.reserve_record() {
+            return core::ptr::null_mut();
+        }
+        #[cfg(feature = "debug-heap")]
+        // SAFETY: Caller upholds the same requirements.
+        let ptr = unsafe { self.debug_alloc(layout) };
+        #[cfg(not(feature = "debug-heap"))]
+        // SAFETY: Caller upholds the same requirements.
+        let ptr = unsafe { self.bump_alloc(layout) };
+        #[cfg(feature = "checked-free")]
+        if ptr.is_null() {
+            // Don’t keep chunk of the records allocated for failed allocation.
+            // SAFETY: Nothing has been allocated since the checkpoint.
+            unsafe { self.rollback(checkpoint) };
+        } else if layout.size() != 0 {
+            self.header().records.push(ptr, layout.size());
+        }
+        #[cfg(feature = "stats")]
+        if !ptr.is_null() {
+            Stats::update(&self.header().stats, |stats| {
+                stats.live_bytes += layout.size()
+            });
+        }
+        // Zero-size blocks take no memory and may share their address with
+        // the next block so they’re left out of the trace.
+        #[cfg(feature = "trace")]
+        if layout.size() != 0 {
+            self.header().trace.record(Event::Alloc {
+                addr: self.trace_addr(ptr),
+                size: layout.size(),
+                align: layout.align(),
+            });
+        }
+        ptr
+    }
+
+    /// Allocates zero-initialised memory.
+    ///
+    /// Solana provides zero-filled heap so only the part of the block which
+    /// has been allocated before (and possibly freed) needs to be zeroed.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let untouched = self.untouched();
+        // SAFETY: Caller guarantees layout has non-zero size.
+        let ptr = unsafe { self.alloc(layout) };
+        if !ptr.is_null() {
+            // SAFETY: We’ve just allocated the block.
+            unsafe { Self::zero_dirty(ptr, layout.size(), untouched) };
+        }
+        ptr
+    }
+
+    /// Deallocates specified object.
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "checked-free")]
+        if layout.size() != 0 {
+            self.check_free(ptr, layout, true);
+        }
+        #[cfg(feature = "stats")]
+        Stats::update(&self.header().stats, |stats| {
+            stats.live_bytes -= layout.size()
+        });
+        // SAFETY: Caller upholds the same requirements.
+        unsafe {
+            #[cfg(feature = "debug-heap")]
+            self.debug_dealloc(ptr, layout);
+            #[cfg(not(feature = "debug-heap"))]
+            self.bump_dealloc(ptr, layout);
+        }
+        #[cfg(feature = "trace")]
+        if layout.size() != 0 {
+            self.header()
+                .trace
+                .record(Event::Dealloc { addr: self.trace_addr(ptr) });
+        }
+    }
+
+    /// Reallocate an object.
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // Zero-size block isn’t recorded.  Make sure there’s space for the
+        // record in case it grows in place.
+        #[cfg(feature = "checked-free")]
+        if layout.size() != 0 {
+            self.check_free(ptr, layout, false);
+        } else if !self.reserve_record() {
+            return core::ptr::null_mut();
+        }
+        // Allocation and deallocation made when moving the block are part of
+        // the reallocation.
+        #[cfg(feature = "trace")]
+        let suspended = self.header().trace.suspend();
+        #[cfg(feature = "debug-heap")]
+        // SAFETY: Caller upholds the same requirements.
+        let new_ptr = unsafe { self.debug_realloc(ptr, layout, new_size) };
+        #[cfg(not(feature = "debug-heap"))]
+        // SAFETY: Caller upholds the same requirements.
+        let new_ptr = unsafe { self.bump_realloc(ptr, layout, new_size) };
+        // If the block has moved, alloc and dealloc have updated the records
+        // and the statistics.
+        if new_ptr == ptr {
+            #[cfg(feature = "checked-free")]
+            if layout.size() != 0 {
+                self.header().records.resize(ptr, new_size);
+            } else {
+                self.header().records.push(ptr, new_size);
+            }
+            #[cfg(feature = "stats")]
+            Stats::update(&self.header().stats, |stats| {
+                stats.live_bytes = stats.live_bytes - layout.size() + new_size
+            });
+        }
+        #[cfg(feature = "trace")]
+        {
+            let trace = &self.header().trace;
+            trace.resume(suspended);
+            // Zero-size block isn’t in the trace so growing it is recorded as
+            // an allocation.
+            let addr = self.trace_addr(ptr);
+            let new_addr = self.trace_addr(new_ptr);
+            trace.record(if layout.size() == 0 {
+                Event::Alloc {
+                    addr: new_addr,
+                    size: new_size,
+                    align: layout.align(),
+                }
+            } else {
+                Event::Realloc { addr, new_size, new_addr }
+            });
+        }
+        new_ptr
     }
 }
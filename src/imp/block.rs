@@ -0,0 +1,74 @@
+//! Per-block metadata used when `reclaim` feature is enabled.
+
+use core::cell::Cell;
+
+/// Metadata stored directly in front of each allocated block.
+///
+/// Blocks form a singly-linked list going from the most recently allocated
+/// block (stored in the allocator’s header) towards the oldest one.  This
+/// allows the allocator to roll back end position over blocks which have been
+/// freed out of order once the block at the tail of the heap is freed.
+pub(super) struct Block {
+    /// End position before the block was allocated.  Once the block is
+    /// released, this is where the end position is moved back to.
+    start: Cell<*mut u8>,
+    /// Previous block.  Lowest bit is set if this block has been freed.
+    prev: Cell<*mut Block>,
+}
+
+impl Block {
+    /// Size of the metadata.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Alignment of the metadata.
+    pub const ALIGN: usize = core::mem::align_of::<Self>();
+
+    /// Initialises metadata of a block whose data starts at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned to [`Self::ALIGN`] and there must be
+    /// [`Self::SIZE`] bytes of heap memory we own in front of it.
+    pub unsafe fn init<'a>(
+        ptr: *mut u8,
+        start: *mut u8,
+        prev: *mut Block,
+    ) -> &'a Self {
+        let block = ptr.wrapping_sub(Self::SIZE).cast::<Self>();
+        // SAFETY: Caller guarantees the memory is ours and aligned.
+        unsafe {
+            block
+                .write(Self { start: Cell::new(start), prev: Cell::new(prev) });
+            &*block
+        }
+    }
+
+    /// Returns metadata of a block whose data starts at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by the allocator and the block must not
+    /// have been released.
+    pub unsafe fn of<'a>(ptr: *mut u8) -> &'a Self {
+        // SAFETY: Caller guarantees `ptr` points to live block’s data.
+        unsafe { &*ptr.wrapping_sub(Self::SIZE).cast::<Self>() }
+    }
+
+    /// Returns end position from before the block was allocated.
+    pub fn start(&self) -> *mut u8 { self.start.get() }
+
+    /// Returns pointer to the previous block.
+    pub fn prev(&self) -> *mut Block {
+        let prev = self.prev.get().cast::<u8>();
+        crate::ptr::with_addr(prev, prev as usize & !1).cast()
+    }
+
+    /// Returns whether the block has been freed.
+    pub fn is_freed(&self) -> bool { self.prev.get() as usize & 1 != 0 }
+
+    /// Marks the block as freed.
+    pub fn set_freed(&self) {
+        let prev = self.prev.get().cast::<u8>();
+        self.prev.set(crate::ptr::with_addr(prev, prev as usize | 1).cast());
+    }
+}
@@ -0,0 +1,116 @@
+//! Size-class caches used when `size-classes` feature is enabled.
+
+use alloc::alloc::Layout;
+use core::cell::Cell;
+
+/// Smallest size class.  Must be large enough to hold a pointer.
+const MIN_SIZE: usize = 8;
+
+/// Largest size class.
+const MAX_SIZE: usize = 128;
+
+/// Number of size classes: 8, 16, 32, 64 and 128 bytes.
+const COUNT: usize = (MAX_SIZE / MIN_SIZE).trailing_zeros() as usize + 1;
+
+/// Alignment of blocks in the caches.
+const ALIGN: usize = core::mem::align_of::<*mut u8>();
+
+const _: () = assert!(MIN_SIZE >= core::mem::size_of::<*mut u8>());
+
+
+/// Free lists of small blocks.
+///
+/// Small allocations are rounded up to the nearest power of two and, when
+/// freed out of order, put on a singly-linked list for their size class.
+/// Subsequent allocations of the same size class take blocks from the list
+/// before falling back to the bump pointer.  The link to the next free block
+/// is stored in the freed block itself so there’s no per-block overhead.
+pub(super) struct SizeClasses([Cell<*mut u8>; COUNT]);
+
+impl SizeClasses {
+    /// Returns index of size class for given layout or `None` if the
+    /// allocation is not handled by the caches.
+    ///
+    /// Allocations larger than 128 bytes or with alignment greater than
+    /// pointer’s alignment are not cached.
+    fn index(layout: Layout) -> Option<usize> {
+        if layout.size() > MAX_SIZE || layout.align() > ALIGN {
+            return None;
+        }
+        let size = layout.size().max(MIN_SIZE).next_power_of_two();
+        Some((size / MIN_SIZE).trailing_zeros() as usize)
+    }
+
+    /// Returns whether given layout is handled by the caches.
+    #[cfg(not(feature = "debug-heap"))]
+    pub fn is_cached(layout: Layout) -> bool { Self::index(layout).is_some() }
+
+    /// Returns layout of the block actually allocated for given layout.
+    ///
+    /// For cached allocations, this is the size class with pointer alignment.
+    /// Otherwise, it’s the same layout.
+    pub fn layout(layout: Layout) -> Layout {
+        match Self::index(layout) {
+            // SAFETY: Sizes are small powers of two and ALIGN is a valid
+            // alignment.
+            Some(idx) => unsafe {
+                Layout::from_size_align_unchecked(MIN_SIZE << idx, ALIGN)
+            },
+            None => layout,
+        }
+    }
+
+    /// Takes a cached block for given layout.  Returns null if there are no
+    /// blocks in the cache.
+    pub fn pop(&self, layout: Layout) -> *mut u8 {
+        let Some(head) = Self::index(layout).map(|idx| &self.0[idx]) else {
+            return core::ptr::null_mut();
+        };
+        let ptr = head.get();
+        if !ptr.is_null() {
+            // SAFETY: Blocks on the list are aligned and hold pointer to the
+            // next block.
+            head.set(unsafe { ptr.cast::<*mut u8>().read() });
+        }
+        ptr
+    }
+
+    /// Puts block into the cache.  Returns `false` if the layout isn’t
+    /// handled by the caches.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block allocated with [`Self::layout`] of `layout`
+    /// which is no longer used.
+    pub unsafe fn push(&self, ptr: *mut u8, layout: Layout) -> bool {
+        let Some(head) = Self::index(layout).map(|idx| &self.0[idx]) else {
+            return false;
+        };
+        // SAFETY: Caller guarantees the block is aligned and large enough.
+        unsafe { ptr.cast::<*mut u8>().write(head.get()) };
+        head.set(ptr);
+        true
+    }
+
+    /// Removes from the caches all blocks at or above given address.
+    ///
+    /// This is used when the end position moves back so that blocks in the
+    /// released part of the heap aren’t handed out again.
+    pub fn forget_above(&self, end: *mut u8) {
+        for head in self.0.iter() {
+            let mut link = head;
+            let mut ptr = link.get();
+            while !ptr.is_null() {
+                // SAFETY: Blocks on the list hold pointer to the next block.
+                // Cell<T> has the same memory layout as T.
+                let next = unsafe { &*ptr.cast::<Cell<*mut u8>>() };
+                if ptr >= end {
+                    link.set(next.get());
+                } else {
+                    link = next;
+                }
+                ptr = link.get();
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Redzones, canaries and poisoning used when `debug-heap` feature is enabled.
+
+use alloc::alloc::Layout;
+use core::cell::Cell;
+
+/// Byte written to redzones around allocations and to header canaries.
+const CANARY: u8 = 0xFD;
+
+/// Byte freshly allocated memory is filled with.
+pub(super) const ALLOC_POISON: u8 = 0xCD;
+
+/// Byte freed memory is filled with.
+pub(super) const FREE_POISON: u8 = 0xDD;
+
+/// Size of the redzone following each allocation and of header canaries.
+const REDZONE: usize = 16;
+
+
+/// Canary bytes in the allocator’s header.
+///
+/// The header has one canary between allocator’s data and the global state
+/// and another one after the global state so that out-of-bounds writes to the
+/// global state are detected.
+pub(super) struct Canary([Cell<u8>; REDZONE]);
+
+// SAFETY: Cell<u8> is Zeroable.
+unsafe impl bytemuck::Zeroable for Canary {}
+
+impl Canary {
+    /// Writes the canary bytes.
+    pub fn set(&self) { self.0.iter().for_each(|byte| byte.set(CANARY)); }
+
+    /// Returns whether the canary bytes are intact.
+    pub fn is_intact(&self) -> bool {
+        self.0.iter().all(|byte| byte.get() == CANARY)
+    }
+}
+
+
+/// Metadata stored directly in front of each allocation.
+///
+/// Together with the redzone after the allocation, it forms a guard around
+/// user’s data.  Live allocations form a doubly-linked list which lets
+/// [`Redzones::verify`] check all of them.
+#[repr(C)]
+struct Guard {
+    /// Previously allocated live block.
+    prev: Cell<*mut Guard>,
+    /// Next allocated live block.
+    next: Cell<*mut Guard>,
+    /// Size of the allocation as requested by the user.
+    size: Cell<usize>,
+    /// Canary directly in front of user’s data.
+    canary: Cell<u64>,
+}
+
+impl Guard {
+    const SIZE: usize = core::mem::size_of::<Self>();
+    const ALIGN: usize = core::mem::align_of::<Self>();
+    const CANARY: u64 = u64::from_ne_bytes([CANARY; 8]);
+
+    /// Returns guard of the allocation whose data starts at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Redzones::init`] and the block must
+    /// still be reserved.
+    unsafe fn of<'a>(ptr: *mut u8) -> &'a Self {
+        // SAFETY: Caller guarantees there’s a guard in front of ptr.
+        unsafe { &*ptr.wrapping_sub(Self::SIZE).cast::<Self>() }
+    }
+
+    /// Returns pointer to user’s data.
+    fn data(&self) -> *mut u8 {
+        core::ptr::from_ref(self)
+            .cast::<u8>()
+            .cast_mut()
+            .wrapping_add(Self::SIZE)
+    }
+
+    /// Returns whether the canary and the redzone after the data are intact.
+    fn is_intact(&self) -> bool {
+        if self.canary.get() != Self::CANARY {
+            return false;
+        }
+        let redzone = self.data().wrapping_add(self.size.get());
+        // SAFETY: The redzone is part of the reserved block.
+        let redzone = unsafe { core::slice::from_raw_parts(redzone, REDZONE) };
+        redzone.iter().all(|&byte| byte == CANARY)
+    }
+
+    /// Panics if the guard isn’t intact or doesn’t match the size passed by
+    /// the caller.
+    #[track_caller]
+    fn check(&self, size: usize) {
+        // Compare the size first so that corrupted size doesn’t make us look
+        // for the redzone outside of the block.
+        assert!(
+            self.size.get() == size && self.is_intact(),
+            "heap corruption: redzone of block at {:?} overwritten",
+            self.data(),
+        );
+    }
+}
+
+
+/// List of live allocations with guards around them.
+pub(super) struct Redzones {
+    /// Most recently allocated live block.
+    last: Cell<*mut Guard>,
+}
+
+// SAFETY: Pointers are null when zeroed.
+unsafe impl bytemuck::Zeroable for Redzones {}
+
+impl Redzones {
+    /// Returns layout of the block which needs to be reserved for an
+    /// allocation with given layout.
+    ///
+    /// The block holds the guard, user’s data and the redzone after it.
+    /// Returns `None` if the size overflows.
+    pub fn layout(layout: Layout) -> Option<Layout> {
+        let align = layout.align().max(Guard::ALIGN);
+        let size = Self::offset(align)
+            .checked_add(layout.size())?
+            .checked_add(REDZONE)?;
+        Layout::from_size_align(size, align).ok()
+    }
+
+    /// Returns offset of user’s data within a block of given alignment.
+    fn offset(align: usize) -> usize { Guard::SIZE.next_multiple_of(align) }
+
+    /// Initialises guard in a freshly reserved block and adds it to the list.
+    /// Returns pointer to user’s data which is filled with [`ALLOC_POISON`].
+    ///
+    /// # Safety
+    ///
+    /// `block` must be a block reserved with [`Self::layout`] of `layout`.
+    pub unsafe fn init(&self, block: *mut u8, layout: Layout) -> *mut u8 {
+        let align = layout.align().max(Guard::ALIGN);
+        let ptr = block.wrapping_add(Self::offset(align));
+        let guard = ptr.wrapping_sub(Guard::SIZE).cast::<Guard>();
+        // SAFETY: The block has space for the guard, data and redzone.
+        unsafe {
+            guard.write(Guard {
+                prev: Cell::new(self.last.get()),
+                next: Cell::new(core::ptr::null_mut()),
+                size: Cell::new(layout.size()),
+                canary: Cell::new(Guard::CANARY),
+            });
+            ptr.write_bytes(ALLOC_POISON, layout.size());
+            ptr.add(layout.size()).write_bytes(CANARY, REDZONE);
+        }
+        // SAFETY: Last block is either null or a live block.
+        if let Some(last) = unsafe { self.last.get().as_ref() } {
+            last.next.set(guard);
+        }
+        self.last.set(guard);
+        ptr
+    }
+
+    /// Checks guard of allocation at `ptr`, removes it from the list and
+    /// fills user’s data with [`FREE_POISON`].  Returns pointer to the
+    /// reserved block.
+    ///
+    /// Panics if the guard has been overwritten.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Self::init`] with `layout` and
+    /// mustn’t have been released yet.
+    #[track_caller]
+    pub unsafe fn release(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        // SAFETY: Caller guarantees ptr is a live allocation.
+        let guard = unsafe { Guard::of(ptr) };
+        guard.check(layout.size());
+        self.unlink(guard);
+        // SAFETY: Caller guarantees ptr is a live allocation.
+        unsafe { ptr.write_bytes(FREE_POISON, layout.size()) };
+        let align = layout.align().max(Guard::ALIGN);
+        ptr.wrapping_sub(Self::offset(align))
+    }
+
+    /// Checks guard of allocation at `ptr` and panics if it’s been
+    /// overwritten.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Self::init`] and mustn’t have been
+    /// released yet.
+    #[track_caller]
+    pub unsafe fn check(&self, ptr: *mut u8, size: usize) {
+        // SAFETY: Caller guarantees ptr is a live allocation.
+        unsafe { Guard::of(ptr) }.check(size);
+    }
+
+    /// Removes guard from the list.
+    fn unlink(&self, guard: &Guard) {
+        let (prev, next) = (guard.prev.get(), guard.next.get());
+        // SAFETY: Neighbours are either null or live blocks.
+        unsafe {
+            match next.as_ref() {
+                Some(next) => next.prev.set(prev),
+                None => self.last.set(prev),
+            }
+            if let Some(prev) = prev.as_ref() {
+                prev.next.set(next);
+            }
+        }
+    }
+
+    /// Removes from the list all blocks at or above given address.
+    ///
+    /// This is used when the end position moves back without the blocks being
+    /// freed.
+    pub fn forget_above(&self, end: *mut u8) {
+        let mut guard = self.last.get();
+        // SAFETY: Blocks on the list are live.
+        while let Some(current) = unsafe { guard.as_ref() } {
+            guard = current.prev.get();
+            if current.data() >= end {
+                self.unlink(current);
+            }
+        }
+    }
+
+    /// Checks guards of all live allocations.  `start` and `end` are bounds
+    /// of the allocated part of the heap.
+    ///
+    /// Panics if any guard has been overwritten or the list is corrupted.
+    #[track_caller]
+    pub fn verify(&self, start: *mut u8, end: *mut u8) {
+        let mut guard = self.last.get();
+        let mut next = core::ptr::null_mut();
+        while !guard.is_null() {
+            let addr = guard.cast::<u8>();
+            assert!(
+                addr >= start &&
+                    addr.wrapping_add(Guard::SIZE) <= end &&
+                    addr as usize & (Guard::ALIGN - 1) == 0,
+                "heap corruption: invalid block pointer {guard:?}",
+            );
+            // SAFETY: We’ve checked the pointer is within the heap.
+            let current = unsafe { &*guard };
+            assert!(
+                current.next.get() == next,
+                "heap corruption: guard of block at {:?} overwritten",
+                current.data(),
+            );
+            let available =
+                (end as usize).saturating_sub(current.data() as usize);
+            assert!(
+                current.size.get().saturating_add(REDZONE) <= available &&
+                    current.is_intact(),
+                "heap corruption: redzone of block at {:?} overwritten",
+                current.data(),
+            );
+            next = guard;
+            guard = current.prev.get();
+        }
+    }
+}
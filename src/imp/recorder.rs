@@ -0,0 +1,164 @@
+//! Recording of allocator calls used when `trace` feature is enabled.
+
+use core::cell::Cell;
+
+use crate::trace::{Event, Output};
+
+/// State of a recorder which isn’t recording.
+const STOPPED: u8 = 0;
+/// State of a recorder keeping the trace in its buffer.
+const BUFFER: u8 = 1;
+/// State of a recorder flushing its buffer to logs.
+const LOG: u8 = 2;
+
+
+/// Recorder of allocator calls.
+///
+/// The trace is written to a buffer reserved on the heap.  Zeroed recorder
+/// isn’t recording.
+pub(super) struct Recorder {
+    /// Buffer the trace is written to or null if none has been reserved.
+    buf: Cell<*mut u8>,
+    /// Size of the buffer.
+    capacity: Cell<usize>,
+    /// Length of the trace in the buffer.
+    len: Cell<usize>,
+    /// One of [`STOPPED`], [`BUFFER`] or [`LOG`].
+    state: Cell<u8>,
+    /// Whether recording is suspended while a call is in progress.
+    suspended: Cell<bool>,
+}
+
+// SAFETY: All fields are Zeroable; pointers are null when zeroed.
+unsafe impl bytemuck::Zeroable for Recorder {}
+
+impl Recorder {
+    /// Minimum size of the buffer.  There must be space for the longest
+    /// event and the truncation marker.
+    pub const MIN_CAPACITY: usize = Event::MAX_LEN + 1;
+
+    /// Starts recording to a buffer of given capacity.
+    ///
+    /// If recording is in progress, it’s stopped first.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must point to a block of `capacity` bytes owned by the recorder
+    /// from now on.  `capacity` must be at least [`Self::MIN_CAPACITY`].
+    pub unsafe fn start(&self, buf: *mut u8, capacity: usize, output: Output) {
+        self.stop();
+        self.buf.set(buf);
+        self.capacity.set(capacity);
+        self.len.set(0);
+        self.state.set(match output {
+            Output::Buffer => BUFFER,
+            Output::Log => LOG,
+        });
+    }
+
+    /// Stops recording and returns the trace kept in the buffer.
+    ///
+    /// When flushing to logs, flushes the buffer and returns an empty slice.
+    pub fn stop(&self) -> &[u8] {
+        if self.state.get() == LOG {
+            self.flush();
+        }
+        self.state.set(STOPPED);
+        match self.len.get() {
+            0 => &[],
+            // SAFETY: The buffer is owned by us and first len bytes of it have
+            // been initialised.  Since recording stopped, they won’t be
+            // modified until the recording starts again.
+            len => unsafe { core::slice::from_raw_parts(self.buf.get(), len) },
+        }
+    }
+
+    /// Suspends recording and returns whether it has already been
+    /// suspended.
+    ///
+    /// This is used to avoid recording allocations and deallocations made
+    /// internally by a reallocation.
+    pub fn suspend(&self) -> bool { self.suspended.replace(true) }
+
+    /// Restores suspended state saved by [`Self::suspend`].
+    pub fn resume(&self, suspended: bool) { self.suspended.set(suspended) }
+
+    /// Records an event.
+    pub fn record(&self, event: Event) {
+        let state = self.state.get();
+        if state == STOPPED || self.suspended.get() {
+            return;
+        }
+        let mut bytes = [0; Event::MAX_LEN];
+        let len = event.encode(&mut bytes);
+        let bytes = &bytes[..len];
+        // Keep one byte for the truncation marker.
+        if self.len.get() + bytes.len() >= self.capacity.get() {
+            if state == LOG {
+                self.flush();
+            } else {
+                self.write(&[crate::trace::TRUNCATED]);
+                self.state.set(STOPPED);
+                return;
+            }
+        }
+        self.write(bytes);
+    }
+
+    /// Forgets the buffer if it’s at or above `end`.
+    ///
+    /// This is used on rollback which releases memory of the buffer if it
+    /// has been reserved after a checkpoint.
+    pub fn forget_above(&self, end: *mut u8) {
+        if self.buf.get() >= end {
+            self.state.set(STOPPED);
+            self.buf.set(core::ptr::null_mut());
+            self.len.set(0);
+        }
+    }
+
+    /// Appends bytes to the buffer.  Caller must make sure they fit.
+    fn write(&self, bytes: &[u8]) {
+        let len = self.len.get();
+        // SAFETY: The buffer is owned by us and caller guarantees the bytes
+        // fit.
+        unsafe {
+            crate::ptr::memcpy(
+                self.buf.get().add(len),
+                bytes.as_ptr(),
+                bytes.len(),
+            )
+        };
+        self.len.set(len + bytes.len());
+    }
+
+    /// Writes the trace kept in the buffer to logs and empties the buffer.
+    fn flush(&self) {
+        let len = self.len.replace(0);
+        if len == 0 {
+            return;
+        }
+        // SAFETY: The buffer is owned by us and first len bytes of it have
+        // been initialised.
+        let bytes = unsafe { core::slice::from_raw_parts(self.buf.get(), len) };
+        #[cfg(target_os = "solana")]
+        {
+            let data: [&[u8]; 2] = [crate::trace::MAGIC, bytes];
+            // SAFETY: sol_log_data takes pointer to an array of slices and
+            // its length.
+            unsafe { sol_log_data(data.as_ptr().cast(), data.len() as u64) };
+        }
+        #[cfg(not(target_os = "solana"))]
+        std::eprintln!(
+            "Program data: {} {}",
+            crate::base64::Base64(crate::trace::MAGIC),
+            crate::base64::Base64(bytes),
+        );
+    }
+}
+
+
+#[cfg(target_os = "solana")]
+extern "C" {
+    fn sol_log_data(data: *const u8, len: u64);
+}
@@ -0,0 +1,210 @@
+//! Side table of live allocations used when `checked-free` feature is enabled.
+
+use alloc::alloc::Layout;
+use core::cell::Cell;
+
+/// Number of entries in a single chunk of the table.
+const CAPACITY: usize = 16;
+
+
+/// Record of a live allocation.
+#[derive(Clone, Copy)]
+pub(super) struct Entry {
+    /// Address of the allocation.
+    addr: usize,
+    /// Size of the allocation (saturated to `u32::MAX`).
+    size: u32,
+    /// Sequence number of the allocation.  Used to forget allocations made
+    /// after a checkpoint.
+    seq: u32,
+}
+
+impl Entry {
+    /// Returns size of the allocation or `u32::MAX` if it’s larger than that.
+    pub fn size(&self) -> u32 { self.size }
+}
+
+/// Saturates size of allocation to `u32`.
+pub(super) fn saturate(size: usize) -> u32 {
+    u32::try_from(size).unwrap_or(u32::MAX)
+}
+
+
+/// Fixed-size chunk of the table allocated on the heap.
+struct Chunk {
+    /// Next chunk or null if this is the last one.
+    next: Cell<*mut Chunk>,
+    entries: [Cell<Entry>; CAPACITY],
+}
+
+// SAFETY: Chunk consists of pointers and integers.
+unsafe impl bytemuck::Zeroable for Chunk {}
+
+
+/// Table of live allocations.
+///
+/// The table is stored in chunks allocated on the heap which form
+/// a singly-linked list.  Entries are kept densely packed at the front of the
+/// table; removing an entry moves the last entry into its place.  Chunks are
+/// never freed (unless released by a rollback) and are reused as the number of
+/// live allocations changes.
+pub(super) struct Records {
+    /// First chunk of the table or null if no chunk has been allocated yet.
+    first: Cell<*mut Chunk>,
+    /// Number of entries in the table.
+    len: Cell<usize>,
+    /// Number of chunks in the table.
+    chunks: Cell<usize>,
+    /// Sequence number of the next allocation.
+    seq: Cell<u32>,
+}
+
+// SAFETY: All fields are Zeroable; pointers are null when zeroed.
+unsafe impl bytemuck::Zeroable for Records {}
+
+impl Records {
+    /// Layout of a chunk of the table.
+    pub const CHUNK: Layout = Layout::new::<Chunk>();
+
+    /// Returns whether the table is full and needs another chunk before an
+    /// entry can be added.
+    pub fn is_full(&self) -> bool {
+        self.len.get() == self.chunks.get() * CAPACITY
+    }
+
+    /// Adds a chunk at the end of the table.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a block of [`Self::CHUNK`] layout which is owned by
+    /// the table from now on.
+    pub unsafe fn add_chunk(&self, ptr: *mut u8) {
+        let chunk = ptr.cast::<Chunk>();
+        // SAFETY: Caller guarantees the block is ours.  Chunk consists of
+        // Cells of plain data so zeroed value is valid.
+        unsafe { chunk.write(bytemuck::Zeroable::zeroed()) };
+        match self.chunks.get().checked_sub(1).and_then(|idx| self.chunk(idx)) {
+            Some(last) => last.next.set(chunk),
+            None => self.first.set(chunk),
+        }
+        self.chunks.set(self.chunks.get() + 1);
+    }
+
+    /// Returns sequence number of the next allocation.
+    pub fn seq(&self) -> u32 { self.seq.get() }
+
+    /// Adds entry for allocation at `ptr` of given `size`.
+    ///
+    /// Panics if the table is full.
+    pub fn push(&self, ptr: *mut u8, size: usize) {
+        let len = self.len.get();
+        let seq = self.seq.get();
+        self.entry(len).unwrap().set(Entry {
+            addr: ptr as usize,
+            size: saturate(size),
+            seq,
+        });
+        self.len.set(len + 1);
+        self.seq.set(seq.wrapping_add(1));
+    }
+
+    /// Returns entry of allocation at `ptr` or `None` if there’s no such live
+    /// allocation.
+    pub fn get(&self, ptr: *mut u8) -> Option<Entry> {
+        self.position(ptr).and_then(|idx| self.entry(idx)).map(Cell::get)
+    }
+
+    /// Changes recorded size of allocation at `ptr`.
+    pub fn resize(&self, ptr: *mut u8, size: usize) {
+        if let Some(entry) = self.position(ptr).and_then(|idx| self.entry(idx))
+        {
+            entry.set(Entry { size: saturate(size), ..entry.get() });
+        }
+    }
+
+    /// Removes entry of allocation at `ptr` and returns it or returns `None`
+    /// if there’s no such live allocation.
+    pub fn remove(&self, ptr: *mut u8) -> Option<Entry> {
+        let idx = self.position(ptr)?;
+        let entry = self.entry(idx)?;
+        let removed = entry.get();
+        let last = self.len.get() - 1;
+        entry.set(self.entry(last)?.get());
+        self.len.set(last);
+        Some(removed)
+    }
+
+    /// Forgets allocations made since sequence number `seq` and chunks at or
+    /// above `end`.
+    ///
+    /// This is used on rollback which releases memory of all allocations made
+    /// after a checkpoint.  All remaining entries must fit in chunks below
+    /// `end` which is the case if `end` is the end position from when the
+    /// checkpoint was created.
+    pub fn rollback(&self, seq: u32, end: *mut u8) {
+        // Compact remaining entries.  Chunks above end are still intact so
+        // it’s fine to read from them.
+        let mut len = 0;
+        for idx in 0..self.len.get() {
+            let entry = self.entry(idx).unwrap().get();
+            if entry.seq < seq {
+                self.entry(len).unwrap().set(entry);
+                len += 1;
+            }
+        }
+        self.len.set(len);
+        self.seq.set(seq);
+
+        // Forget released chunks.
+        let mut chunks = 0;
+        let mut link = &self.first;
+        // SAFETY: Chunks on the list are valid.
+        while let Some(chunk) = unsafe { link.get().as_ref() } {
+            if link.get().cast::<u8>() >= end {
+                link.set(core::ptr::null_mut());
+                break;
+            }
+            chunks += 1;
+            link = &chunk.next;
+        }
+        self.chunks.set(chunks);
+        debug_assert!(len <= chunks * CAPACITY);
+    }
+
+    /// Returns index of entry for allocation at `ptr`.
+    fn position(&self, ptr: *mut u8) -> Option<usize> {
+        let addr = ptr as usize;
+        let mut chunk = self.first.get();
+        let mut len = self.len.get();
+        let mut base = 0;
+        // SAFETY: Chunks on the list are valid.
+        while let Some(current) = unsafe { chunk.as_ref() } {
+            let entries = &current.entries[..len.min(CAPACITY)];
+            if let Some(idx) =
+                entries.iter().position(|entry| entry.get().addr == addr)
+            {
+                return Some(base + idx);
+            }
+            len = len.checked_sub(CAPACITY)?;
+            base += CAPACITY;
+            chunk = current.next.get();
+        }
+        None
+    }
+
+    /// Returns chunk at given index.
+    fn chunk(&self, idx: usize) -> Option<&Chunk> {
+        let mut chunk = self.first.get();
+        for _ in 0..idx {
+            // SAFETY: Chunks on the list are valid.
+            chunk = unsafe { chunk.as_ref() }?.next.get();
+        }
+        // SAFETY: Chunks on the list are valid.
+        unsafe { chunk.as_ref() }
+    }
+
+    /// Returns entry at given index.
+    fn entry(&self, idx: usize) -> Option<&Cell<Entry>> {
+        self.chunk(idx / CAPACITY).map(|chunk| &chunk.entries[idx % CAPACITY])
+    }
+}
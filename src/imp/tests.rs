@@ -1,17 +1,22 @@
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::cell::Cell;
 
+use crate::heap::Heap;
+#[cfg(feature = "debug-heap")]
+use crate::imp::debug;
 use crate::{ptr, BumpAllocator};
 
 impl<G: bytemuck::Zeroable> BumpAllocator<G> {
     /// Creates a new allocator with given amount of available memory.
-    fn new(size: usize) -> Self {
-        let layout =
-            Layout::from_size_align(size, core::mem::align_of::<Cell<usize>>())
-                .unwrap();
-        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
-        let ptr = core::ptr::NonNull::new(ptr).unwrap();
-        Self { ptr, layout, _ph: core::marker::PhantomData }
+    ///
+    /// The size includes space for the end position address and global state
+    /// but not for any other allocator’s internal data.  This way amount of
+    /// available memory doesn’t depend on enabled features.
+    pub(crate) fn new(size: usize) -> Self {
+        let internal = core::mem::size_of::<crate::imp::Header<()>>() -
+            core::mem::size_of::<Cell<*mut u8>>();
+        let heap = Heap::new(size + internal);
+        Self { heap, _ph: core::marker::PhantomData }
     }
 
     /// Returns amount of used memory in bytes excluding space used for end
@@ -70,58 +75,62 @@ impl<G: bytemuck::Zeroable> BumpAllocator<G> {
 
 #[test]
 fn test_alloc() {
-    let allocator = BumpAllocator::<()>::new(64);
+    let allocator = BumpAllocator::<()>::new(1024);
     assert_eq!(0, allocator.used());
 
     // Large allocation fails.
-    let large = Layout::from_size_align(64 - 7, 1).unwrap();
+    let large = Layout::from_size_align(1024 - 7, 1).unwrap();
     assert_eq!(None, allocator.check_alloc(large));
+    assert_eq!(0, allocator.used());
 
     // Two successful allocations.  Cannot overlap.
     let layout_align_1 = Layout::from_size_align(9, 1).unwrap();
     let layout_align_4 = Layout::from_size_align(8, 4).unwrap();
 
     let first = allocator.check_alloc(layout_align_1).unwrap();
-    assert_eq!(9, allocator.used());
+    let used = allocator.used();
+    assert!(used >= 9);
 
     let second = allocator.check_alloc(layout_align_4).unwrap();
-    assert_eq!(20, allocator.used());
+    assert!(allocator.used() >= used + 8);
     ptr::assert_no_overlap(first, 9, second, 8);
 }
 
 #[test]
 fn test_dealloc() {
-    let allocator = BumpAllocator::<()>::new(64);
-    assert_eq!(0, allocator.used());
-
+    let allocator = BumpAllocator::<()>::new(1024);
     let layout = Layout::array::<u8>(10).unwrap();
 
     let first = allocator.check_alloc(layout).unwrap();
-    assert_eq!(10, allocator.used());
+    let used = allocator.used();
 
     let second = allocator.check_alloc(layout).unwrap();
-    assert_eq!(20, allocator.used());
+    let grown = allocator.used();
+    assert!(grown >= used + 10);
     ptr::assert_no_overlap(first, 10, second, 10);
 
     // Freeing last allocation recovers the memory.
     unsafe { allocator.dealloc(second, layout) };
-    assert_eq!(10, allocator.used());
+    assert!(allocator.used() < grown);
 
     let third = unsafe { allocator.alloc(layout) };
     assert_eq!(second, third);
+    assert_eq!(grown, allocator.used());
 
-    // Freeing from the middle wastes memory.
+    // Freeing from the middle wastes memory unless `reclaim` releases it once
+    // blocks after it are freed or `size-classes` caches it.
     unsafe {
         allocator.dealloc(first, layout);
         allocator.dealloc(third, layout);
     }
-    assert_eq!(10, allocator.used());
+    let fourth = allocator.check_alloc(layout).unwrap();
+    let reused = cfg!(any(feature = "reclaim", feature = "size-classes"));
+    assert_eq!(reused, fourth == first);
 }
 
 #[test]
 fn test_realloc() {
-    let allocator = BumpAllocator::<()>::new(64);
-    assert_eq!(0, allocator.used());
+    let allocator = BumpAllocator::<()>::new(1024);
 
     let layout_5 = Layout::array::<u8>(5).unwrap();
     let layout_10 = Layout::array::<u8>(10).unwrap();
@@ -131,43 +140,51 @@ fn test_realloc() {
     let second = allocator.check_alloc(layout_10).unwrap();
 
     // Resizing last allocation always works (so long there’s free memory).
-    assert_eq!(second, allocator.check_realloc(second, layout_10, 15).unwrap());
-    assert_eq!(25, allocator.used());
-    assert_eq!(second, allocator.check_realloc(second, layout_15, 5).unwrap());
-    assert_eq!(15, allocator.used());
+    // With `debug-heap`, blocks are moved whenever their size changes.
+    let moves = cfg!(feature = "debug-heap");
+    let used = allocator.used();
+    let grown = allocator.check_realloc(second, layout_10, 15).unwrap();
+    assert_eq!(moves, grown != second);
+    let shrunk = allocator.check_realloc(grown, layout_15, 5).unwrap();
+    assert_eq!(moves, shrunk != grown);
+    if !moves {
+        assert!(allocator.used() < used);
+    }
 
     // Shrinking always works but the memory is wasted.
-    assert_eq!(first, allocator.check_realloc(first, layout_10, 5).unwrap());
-    assert_eq!(15, allocator.used());
-
-    // Growing region in the middle requires copying.
-    unsafe { first.write_bytes(42, 5) };
-    let third = allocator.check_realloc(first, layout_5, 10).unwrap();
-    assert_ne!(first, third);
-    let slice = unsafe { core::slice::from_raw_parts_mut(first, 10) };
-    assert_eq!([42, 42, 42, 42, 42, 0, 0, 0, 0, 0], slice);
+    let shrunk = allocator.check_realloc(first, layout_10, 5).unwrap();
+    assert_eq!(moves, shrunk != first);
+
+    // Growing region in the middle requires copying.  Unless freed memory is
+    // reused for a cache or filled, the old data stays behind.
+    unsafe { shrunk.write_bytes(42, 5) };
+    let third = allocator.check_realloc(shrunk, layout_5, 10).unwrap();
+    assert_ne!(shrunk, third);
+    if !cfg!(any(feature = "size-classes", feature = "debug-heap")) {
+        let slice = unsafe { core::slice::from_raw_parts_mut(shrunk, 10) };
+        assert_eq!([42, 42, 42, 42, 42, 0, 0, 0, 0, 0], slice);
+    }
 }
 
 #[test]
 fn test_realloc_with_alloc() {
-    let allocator = BumpAllocator::<()>::new(64);
-    assert_eq!(0, allocator.used());
-
+    let allocator = BumpAllocator::<()>::new(1024);
     let layout = Layout::from_size_align(5, 4).unwrap();
 
     let first = allocator.check_alloc(layout).unwrap();
-    assert_eq!(5, allocator.used());
-
-    let _ = allocator.check_alloc(layout);
-    assert_eq!(13, allocator.used());
+    let second = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
 
-    let _ = allocator.check_realloc(first, layout, 10).unwrap();
-    assert_eq!(26, allocator.used());
+    // Growing a block which isn’t the last one moves it to the end.
+    let third = allocator.check_realloc(first, layout, 10).unwrap();
+    assert_ne!(first, third);
+    assert!(allocator.used() >= used + 10);
+    ptr::assert_no_overlap(second, 5, third, 10);
 }
 
 #[test]
 fn test_global() {
-    let allocator = BumpAllocator::<Cell<usize>>::new(64);
+    let allocator = BumpAllocator::<Cell<usize>>::new(1024);
 
     // Global state is always available
     let global = allocator.global();
@@ -176,13 +193,13 @@ fn test_global() {
     assert_eq!(42, global.get());
 
     // Global state consumes space so largest possible allocation shrinks.
-    let large = Layout::from_size_align(64 - 15, 1).unwrap();
+    let large = Layout::from_size_align(1024 - 15, 1).unwrap();
     assert_eq!(None, allocator.check_alloc(large));
 
     // Global state doesn’t overlap with allocations.
     let layout = Layout::from_size_align(8, 1).unwrap();
     let ptr = allocator.check_alloc(layout).unwrap();
-    assert_eq!(8, allocator.used());
+    assert!(allocator.used() >= 8);
     ptr::assert_no_overlap(
         core::ptr::addr_of!(*global).cast(),
         core::mem::size_of_val(global),
@@ -194,6 +211,39 @@ fn test_global() {
     assert!(core::ptr::eq(global, allocator.global()));
 }
 
+#[test]
+fn test_with_global_mut() {
+    #[derive(bytemuck::Zeroable)]
+    struct Global {
+        counter: u32,
+        data: [u64; 4],
+    }
+
+    let allocator = BumpAllocator::<Global>::new(1024);
+    allocator.with_global_mut(|global| {
+        global.counter += 1;
+        global.data[1] = 42;
+        // Nested access is rejected.
+        assert_eq!(None, allocator.try_with_global_mut(|_| ()));
+    });
+    let counter = allocator.with_global_mut(|global| {
+        global.counter += 1;
+        global.counter
+    });
+    assert_eq!(2, counter);
+
+    // Once shared reference is handed out, mutable borrows are rejected.
+    assert_eq!([0, 42, 0, 0], allocator.global().data);
+    assert_eq!(None, allocator.try_with_global_mut(|_| ()));
+}
+
+#[test]
+#[should_panic = "global state already mutably borrowed"]
+fn test_with_global_mut_shared() {
+    let allocator = BumpAllocator::<u64>::new(1024);
+    allocator.with_global_mut(|_| allocator.global());
+}
+
 #[test]
 #[should_panic]
 fn test_global_too_large() {
@@ -201,3 +251,679 @@ fn test_global_too_large() {
     // The global state is too large.
     let _global = allocator.global();
 }
+
+#[test]
+fn test_checkpoint() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::array::<u8>(16).unwrap();
+
+    let first = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
+    let checkpoint = allocator.checkpoint();
+
+    // Objects freed out of order are normally leaked (unless `reclaim`
+    // releases them; with `size-classes` small blocks are cached instead)…
+    let reclaimed =
+        cfg!(all(feature = "reclaim", not(feature = "size-classes")));
+    let second = allocator.check_alloc(layout).unwrap();
+    let third = allocator.check_alloc(layout).unwrap();
+    unsafe {
+        allocator.dealloc(second, layout);
+        allocator.dealloc(third, layout);
+    }
+    assert_eq!(reclaimed, used == allocator.used());
+
+    // …but rolling back to a checkpoint recovers the memory.
+    unsafe { allocator.rollback(checkpoint) };
+    assert_eq!(used, allocator.used());
+    assert_eq!(second, allocator.check_alloc(layout).unwrap());
+
+    // Rollback doesn’t move end position forward if memory allocated before
+    // the checkpoint has been freed.
+    let checkpoint = allocator.checkpoint();
+    unsafe {
+        allocator.dealloc(second, layout);
+        allocator.dealloc(first, layout);
+    }
+    let freed = allocator.used();
+    assert!(freed < used);
+    unsafe { allocator.rollback(checkpoint) };
+    assert_eq!(freed, allocator.used());
+}
+
+#[test]
+fn test_with_scope() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::array::<u8>(16).unwrap();
+
+    let reclaimed =
+        cfg!(all(feature = "reclaim", not(feature = "size-classes")));
+    let first = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
+    let value = unsafe {
+        allocator.with_scope(|| {
+            let a = allocator.check_alloc(layout).unwrap();
+            let b = allocator.check_alloc(layout).unwrap();
+            allocator.dealloc(a, layout);
+            allocator.dealloc(b, layout);
+            assert_eq!(reclaimed, used == allocator.used());
+            42
+        })
+    };
+    assert_eq!(42, value);
+    assert_eq!(used, allocator.used());
+    unsafe { allocator.dealloc(first, layout) };
+    assert!(allocator.used() < used);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn test_rollback_live_object() {
+    let allocator = BumpAllocator::<()>::new(64);
+    let layout = Layout::array::<u8>(10).unwrap();
+
+    let checkpoint = allocator.checkpoint();
+    let _leaked = allocator.check_alloc(layout).unwrap();
+    unsafe { allocator.rollback(checkpoint) };
+}
+
+#[test]
+#[cfg(all(
+    feature = "reclaim",
+    not(any(feature = "debug-heap", feature = "checked-free"))
+))]
+fn test_reclaim() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    // Use large blocks so that they aren’t handled by size-classes.
+    let layout = Layout::array::<u8>(200).unwrap();
+
+    let first = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
+    let second = allocator.check_alloc(layout).unwrap();
+    let third = allocator.check_alloc(layout).unwrap();
+    let fourth = allocator.check_alloc(layout).unwrap();
+
+    // Freeing from the middle doesn’t recover memory…
+    unsafe {
+        allocator.dealloc(second, layout);
+        allocator.dealloc(third, layout);
+    }
+    assert_ne!(used, allocator.used());
+
+    // …until the last block is freed.
+    unsafe { allocator.dealloc(fourth, layout) };
+    assert_eq!(used, allocator.used());
+    assert_eq!(second, allocator.check_alloc(layout).unwrap());
+
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(second, layout);
+    }
+    assert_eq!(0, allocator.used());
+}
+
+#[test]
+#[cfg(all(
+    feature = "reclaim",
+    not(any(feature = "debug-heap", feature = "checked-free"))
+))]
+fn test_reclaim_realloc() {
+    let allocator = BumpAllocator::<()>::new(512);
+    let layout = Layout::from_size_align(10, 16).unwrap();
+
+    // Growing a block in the middle frees the old block.
+    let first = allocator.check_alloc(layout).unwrap();
+    let second = allocator.check_alloc(layout).unwrap();
+    let first = allocator.check_realloc(first, layout, 20).unwrap();
+    unsafe { allocator.dealloc(second, layout) };
+    unsafe {
+        allocator.dealloc(first, Layout::from_size_align(20, 16).unwrap())
+    };
+    assert_eq!(0, allocator.used());
+
+    // Resizing the last block keeps it in place.
+    let first = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
+    assert_eq!(first, allocator.check_realloc(first, layout, 20).unwrap());
+    assert_eq!(used + 10, allocator.used());
+}
+
+#[test]
+#[cfg(feature = "reclaim")]
+fn test_reclaim_rollback() {
+    let allocator = BumpAllocator::<()>::new(2048);
+    // Use large blocks so that they aren’t handled by size-classes.
+    let layout = Layout::array::<u8>(200).unwrap();
+
+    let _first = allocator.check_alloc(layout).unwrap();
+    let used = allocator.used();
+    let second = allocator.check_alloc(layout).unwrap();
+    let checkpoint = allocator.checkpoint();
+    let _third = allocator.check_alloc(layout).unwrap();
+
+    // Last block from before the checkpoint is freed after the checkpoint.
+    // Rollback releases it as well.
+    unsafe { allocator.dealloc(second, layout) };
+    unsafe { allocator.rollback(checkpoint) };
+    assert_eq!(used, allocator.used());
+}
+
+#[test]
+#[cfg(all(feature = "size-classes", not(feature = "debug-heap")))]
+fn test_size_classes() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let pubkey = Layout::new::<[u8; 32]>();
+    let small = Layout::array::<u8>(20).unwrap();
+
+    let first = allocator.check_alloc(pubkey).unwrap();
+    let second = allocator.check_alloc(pubkey).unwrap();
+    let _third = allocator.check_alloc(pubkey).unwrap();
+    let used = allocator.used();
+
+    // Freed small blocks are reused by allocations of the same size class.
+    unsafe {
+        allocator.dealloc(first, pubkey);
+        allocator.dealloc(second, pubkey);
+    }
+    assert_eq!(second, allocator.check_alloc(small).unwrap());
+    assert_eq!(first, allocator.check_alloc(pubkey).unwrap());
+    assert_eq!(used, allocator.used());
+
+    // Other size classes are not affected.
+    unsafe { allocator.dealloc(first, pubkey) };
+    let tiny = Layout::new::<u64>();
+    let fourth = allocator.check_alloc(tiny).unwrap();
+    assert_ne!(first, fourth);
+    assert!(used < allocator.used());
+
+    // Resizing within the same size class doesn’t move the block.
+    assert_eq!(second, allocator.check_realloc(second, small, 32).unwrap());
+
+    // Turning large block into a small one moves it.
+    let large = Layout::array::<u8>(200).unwrap();
+    let fifth = allocator.check_alloc(large).unwrap();
+    let _sixth = allocator.check_alloc(tiny).unwrap();
+    assert_eq!(first, allocator.check_realloc(fifth, large, 32).unwrap());
+}
+
+#[test]
+#[cfg(feature = "size-classes")]
+fn test_size_classes_rollback() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::new::<[u8; 32]>();
+
+    let first = allocator.check_alloc(layout).unwrap();
+    let _second = allocator.check_alloc(layout).unwrap();
+    let checkpoint = allocator.checkpoint();
+    let third = allocator.check_alloc(layout).unwrap();
+    let _fourth = allocator.check_alloc(layout).unwrap();
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(third, layout);
+        allocator.rollback(checkpoint);
+    }
+
+    // Cached block above the checkpoint is forgotten; the one below isn’t.
+    assert_eq!(first, allocator.check_alloc(layout).unwrap());
+    assert_eq!(third, allocator.check_alloc(layout).unwrap());
+    let _ = allocator.check_alloc(layout).unwrap();
+}
+
+#[test]
+#[cfg(feature = "debug-heap")]
+fn test_debug_heap() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+
+    // Fresh memory is filled with a pattern.
+    let first = allocator.check_alloc(layout).unwrap();
+    let data = unsafe { core::slice::from_raw_parts(first, 20) };
+    assert!(data.iter().all(|&byte| byte == debug::ALLOC_POISON));
+    unsafe { first.write_bytes(0xAA, 20) };
+
+    // Resizing moves the block.
+    let second = allocator.check_alloc(layout).unwrap();
+    let second = allocator.check_realloc(second, layout, 40).unwrap();
+    allocator.verify_heap();
+
+    // Freed memory is poisoned.
+    unsafe { allocator.dealloc(first, layout) };
+    let data = unsafe { core::slice::from_raw_parts(first, 20) };
+    assert!(data.iter().all(|&byte| byte == debug::FREE_POISON));
+    allocator.verify_heap();
+
+    // Rollback forgets released blocks.  Global slots aren’t freed so use
+    // one to have a live block released by the rollback.
+    let registry = allocator.registry();
+    let alloc = |layout| unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.with_scope(|| {
+            registry.get_or_insert(&0u8, layout, alloc);
+        })
+    };
+    let third = allocator.check_alloc(layout).unwrap();
+    allocator.verify_heap();
+    unsafe { allocator.dealloc(third, layout) };
+
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    unsafe { allocator.dealloc(second, layout) };
+    allocator.verify_heap();
+}
+
+#[test]
+#[cfg(feature = "debug-heap")]
+#[should_panic(expected = "heap corruption: redzone of block")]
+fn test_debug_heap_overflow() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    unsafe { ptr.add(20).write(0) };
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+#[cfg(feature = "debug-heap")]
+#[should_panic(expected = "heap corruption: redzone of block")]
+fn test_debug_heap_underflow() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    let _other = allocator.check_alloc(layout).unwrap();
+    unsafe { ptr.sub(1).write(0) };
+    allocator.verify_heap();
+}
+
+#[test]
+#[cfg(feature = "debug-heap")]
+#[should_panic(expected = "heap corruption: allocator header overwritten")]
+fn test_debug_heap_global_overflow() {
+    let allocator = BumpAllocator::<[u8; 8]>::new(1024);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+    let _ptr = allocator.check_alloc(layout).unwrap();
+    let global = allocator.header().global.get().as_ptr().cast_mut();
+    unsafe { global.add(8).write(0) };
+    allocator.verify_heap();
+}
+
+#[test]
+#[cfg(feature = "checked-free")]
+fn test_checked_free() {
+    let allocator = BumpAllocator::<()>::new(8192);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+
+    // Enough allocations to need more than one chunk of records.
+    let ptrs: alloc::vec::Vec<_> =
+        (0..40).map(|_| allocator.check_alloc(layout).unwrap()).collect();
+    let ptr = allocator.check_realloc(ptrs[0], layout, 10).unwrap();
+    let small = Layout::from_size_align(10, 8).unwrap();
+    let ptr = allocator.check_realloc(ptr, small, 30).unwrap();
+    unsafe { allocator.dealloc(ptr, Layout::from_size_align(30, 8).unwrap()) };
+    for &ptr in ptrs[1..].iter().rev() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // Allocations made after a checkpoint are forgotten by rollback.  Use
+    // a global slot since it isn’t freed.
+    let first = allocator.check_alloc(layout).unwrap();
+    let checkpoint = allocator.checkpoint();
+    let slot = Cell::new(core::ptr::null_mut());
+    allocator.registry().get_or_insert(&0u8, layout, |layout| {
+        slot.set(unsafe { allocator.alloc(layout) });
+        slot.get()
+    });
+    let slot = slot.get();
+    assert!(allocator.header().records.get(slot).is_some());
+    unsafe { allocator.rollback(checkpoint) };
+    assert!(allocator.header().records.get(slot).is_none());
+    unsafe { allocator.dealloc(first, layout) };
+}
+
+#[test]
+#[cfg(feature = "checked-free")]
+fn test_checked_free_zero_size() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let empty = Layout::from_size_align(0, 8).unwrap();
+    let layout = Layout::from_size_align(16, 8).unwrap();
+
+    // Zero-size block may share its address with the next block.
+    let zst = allocator.check_alloc(empty).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    unsafe { allocator.dealloc(ptr, layout) };
+    unsafe { allocator.dealloc(zst, empty) };
+
+    // Zero-size block at the end grows in place and gets recorded.
+    let zst = allocator.check_alloc(empty).unwrap();
+    let ptr = allocator.check_realloc(zst, empty, 16).unwrap();
+    assert!(allocator.header().records.get(ptr).is_some());
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+#[cfg(feature = "checked-free")]
+#[should_panic(expected = "not a live allocation (double free?)")]
+fn test_checked_free_double_free() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(20, 8).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    let _other = allocator.check_alloc(layout).unwrap();
+    unsafe { allocator.dealloc(ptr, layout) };
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+#[cfg(feature = "checked-free")]
+#[should_panic(expected = "allocated with size 20")]
+fn test_checked_free_wrong_layout() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let ptr = allocator.check_alloc(Layout::new::<[u8; 20]>()).unwrap();
+    unsafe { allocator.dealloc(ptr, Layout::new::<[u8; 21]>()) };
+}
+
+#[test]
+#[cfg(feature = "checked-free")]
+#[should_panic(expected = "not a heap allocation")]
+fn test_checked_free_global() {
+    let allocator = BumpAllocator::<u64>::new(1024);
+    let _ptr = allocator.check_alloc(Layout::new::<u64>()).unwrap();
+    let ptr = core::ptr::from_ref(allocator.global()).cast_mut();
+    unsafe { allocator.dealloc(ptr.cast(), Layout::new::<u64>()) };
+}
+
+#[test]
+fn test_heap_size() {
+    let allocator = BumpAllocator::<()>::new(512);
+    let layout = Layout::array::<u8>(10).unwrap();
+
+    // Declare heap to end right after the first allocation.
+    let first = allocator.try_alloc(layout).unwrap().as_ptr();
+    let end = allocator.header().end_pos.get();
+    allocator.set_heap_size(end as usize - allocator.heap.start() as usize);
+
+    // Subsequent allocations fail…
+    let err = allocator.try_alloc(layout).unwrap_err();
+    assert_eq!(layout, err.layout());
+    assert_eq!(
+        "memory allocation of 10 bytes (aligned to 1) failed",
+        err.to_string()
+    );
+    assert!(allocator.check_alloc(Layout::new::<u8>()).is_none());
+
+    // …unless memory is freed.
+    unsafe { allocator.dealloc(first, layout) };
+    assert_eq!(first, allocator.try_alloc(layout).unwrap().as_ptr());
+
+    // Resetting the size falls back to the actual size of the heap.
+    allocator.set_heap_size(0);
+    assert!(allocator.try_alloc(layout).is_ok());
+}
+
+#[test]
+fn test_heap_size_too_large() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let remaining = allocator.remaining();
+
+    // Declared size cannot extend the heap past its buffer.
+    allocator.set_heap_size(1 << 20);
+    assert_eq!(remaining, allocator.remaining());
+    let layout = Layout::array::<u8>(100_000).unwrap();
+    assert_eq!(layout, allocator.try_alloc(layout).unwrap_err().layout());
+}
+
+#[test]
+fn test_heap_size_from_sysvar() {
+    use crate::sysvar::INSTRUCTIONS_SYSVAR_ID;
+
+    let allocator = BumpAllocator::<()>::new(64 * 1024);
+    let remaining = allocator.remaining().unwrap();
+    assert_eq!(64 * 1024 - core::mem::size_of::<*mut u8>(), remaining);
+    let reserved = allocator.heap.safe_end() as usize -
+        allocator.heap.start() as usize -
+        remaining;
+
+    // Sysvar with no instructions.
+    let data = [0, 0, 0, 0];
+    assert_eq!(None, allocator.set_heap_size_from_sysvar(&[0; 32], &data));
+    assert_eq!(
+        None,
+        allocator.set_heap_size_from_sysvar(&INSTRUCTIONS_SYSVAR_ID, &[1, 0])
+    );
+    assert_eq!(Some(remaining), allocator.remaining());
+    assert_eq!(
+        Some(32 * 1024),
+        allocator.set_heap_size_from_sysvar(&INSTRUCTIONS_SYSVAR_ID, &data)
+    );
+    assert_eq!(Some(32 * 1024 - reserved), allocator.remaining());
+
+    // Declared size is used when allocating.
+    allocator.set_heap_size(512);
+    let remaining = allocator.remaining().unwrap();
+    let layout = Layout::from_size_align(remaining + 1, 1).unwrap();
+    assert!(allocator.try_alloc(layout).is_err());
+    assert_eq!(Some(remaining), allocator.remaining());
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats() {
+    let allocator = BumpAllocator::<()>::new(2048);
+    let stats = allocator.stats();
+    assert!(stats.reserved >= core::mem::size_of::<*mut u8>());
+    let reserved = stats.reserved;
+    assert_eq!(crate::Stats { reserved, ..Default::default() }, stats);
+
+    // Large enough not to be handled by size classes.
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let first = allocator.check_alloc(layout).unwrap();
+    let second = allocator.check_alloc(layout).unwrap();
+    let stats = allocator.stats();
+    assert_eq!((2, 0, 0), (stats.allocs, stats.deallocs, stats.reallocs));
+    assert_eq!((2, 400), (stats.live(), stats.live_bytes));
+    assert_eq!(allocator.used(), stats.used);
+    assert_eq!(stats.used, stats.peak);
+    let peak = stats.peak;
+
+    // Freeing out of order leaks memory unless `reclaim` is enabled.
+    unsafe { allocator.dealloc(first, layout) };
+    let stats = allocator.stats();
+    assert_eq!(1, stats.deallocs);
+    assert_eq!((1, 200), (stats.live(), stats.live_bytes));
+    assert_eq!(stats.used - 200, stats.unreclaimed());
+    if cfg!(feature = "reclaim") {
+        assert_eq!((1, 0), (stats.reclaimed, stats.leaked));
+    } else {
+        assert_eq!((0, 1), (stats.reclaimed, stats.leaked));
+    }
+
+    // Freeing the last block reclaims memory.  Peak usage stays the same.
+    unsafe { allocator.dealloc(second, layout) };
+    let stats = allocator.stats();
+    assert_eq!(2, stats.deallocs);
+    assert_eq!(stats.deallocs, stats.reclaimed + stats.leaked);
+    assert_eq!(allocator.used(), stats.used);
+    assert!(stats.used < peak);
+    assert_eq!(peak, stats.peak);
+
+    // Moving reallocation counts as allocation and deallocation.  With
+    // `debug-heap` even the in-place growth of the last block moves it.
+    let ptr = allocator.check_alloc(layout).unwrap();
+    let ptr = allocator.check_realloc(ptr, layout, 300).unwrap();
+    let _other = allocator.check_alloc(layout).unwrap();
+    allocator.check_realloc(ptr, Layout::from_size_align(300, 8).unwrap(), 400);
+    let stats = allocator.stats();
+    let moved = usize::from(cfg!(feature = "debug-heap"));
+    assert_eq!(
+        (5 + moved, 3 + moved, 2),
+        (stats.allocs, stats.deallocs, stats.reallocs)
+    );
+    assert_eq!((2, 600), (stats.live(), stats.live_bytes));
+    assert_eq!(allocator.used(), stats.used);
+    assert_eq!(stats.used, stats.peak);
+
+    // Alignment padding is accounted for.  Block metadata and redzones would
+    // sit between the old end and the pointer so only check plain blocks.
+    if !cfg!(any(feature = "reclaim", feature = "debug-heap")) {
+        let padding = stats.padding;
+        let end = allocator.header().end_pos.get();
+        let ptr =
+            allocator.check_alloc(Layout::from_size_align(8, 64).unwrap());
+        let expected = ptr.unwrap() as usize - end as usize;
+        assert_eq!(padding + expected, allocator.stats().padding);
+    }
+}
+
+#[test]
+fn test_alloc_zeroed() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(200, 8).unwrap();
+
+    // Memory which has been used before is zeroed.
+    let ptr = allocator.check_alloc(layout).unwrap();
+    unsafe { ptr.write_bytes(0xAA, layout.size()) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    let ptr = unsafe { allocator.alloc_zeroed(layout) };
+    let data = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+    assert!(data.iter().all(|&byte| byte == 0));
+
+    // Memory which has never been used is not.  Scribble over it to check
+    // that the allocator doesn’t write to it.  With `debug-heap`, the
+    // allocator fills new blocks so they are always zeroed.
+    if cfg!(feature = "debug-heap") {
+        return;
+    }
+    let end = allocator.header().end_pos.get();
+    unsafe { end.write_bytes(0xAA, 64) };
+    let small = Layout::from_size_align(8, 1).unwrap();
+    let ptr = unsafe { allocator.alloc_zeroed(small) };
+    assert!(end <= ptr && ptr.wrapping_add(8) <= end.wrapping_add(64));
+    let data = unsafe { core::slice::from_raw_parts(ptr, small.size()) };
+    assert_eq!(&[0xAA; 8], data);
+}
+
+#[test]
+fn test_realloc_zeroed() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let ptr = allocator.check_alloc(layout).unwrap();
+    unsafe { ptr.write_bytes(0xAA, layout.size()) };
+    unsafe { allocator.dealloc(ptr, layout) };
+
+    // Grown part of the block is zeroed while the old data is kept.
+    let small = Layout::from_size_align(150, 8).unwrap();
+    let ptr = allocator.check_alloc(small).unwrap();
+    unsafe { ptr.write_bytes(0x55, small.size()) };
+    let ptr = unsafe { allocator.realloc_zeroed(ptr, small, 300) };
+    let data = unsafe { core::slice::from_raw_parts(ptr, 300) };
+    assert!(data[..150].iter().all(|&byte| byte == 0x55));
+    assert!(data[150..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_rollback_slots() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let registry = allocator.registry();
+    let layout = Layout::new::<u64>();
+    let alloc = |layout| unsafe { allocator.alloc_zeroed(layout) };
+    let keys = [0u8, 1u8];
+
+    let first = registry.get_or_insert(&keys[0], layout, alloc);
+    let used = allocator.used();
+    unsafe {
+        allocator.with_scope(|| {
+            let ptr = registry.get_or_insert(&keys[1], layout, alloc);
+            ptr.cast::<u64>().write(42);
+        })
+    };
+    assert_eq!(used, allocator.used());
+
+    // Slot registered in the scope is reset while the other one is kept.
+    let fail = |_| -> *mut u8 { panic!() };
+    assert_eq!(first, registry.get_or_insert(&keys[0], layout, fail));
+    let second = registry.get_or_insert(&keys[1], layout, alloc);
+    assert_eq!(0, unsafe { second.cast::<u64>().read() });
+}
+
+#[test]
+fn test_rollback_slots_nested() {
+    let allocator = BumpAllocator::<()>::new(1024);
+    let registry = allocator.registry();
+    let layout = Layout::new::<u64>();
+    let alloc = |layout| unsafe { allocator.alloc_zeroed(layout) };
+    let used = allocator.used();
+
+    // Slot registered in the inner scope is forgotten by its rollback so the
+    // outer rollback doesn’t see it as a live object.
+    unsafe {
+        allocator.with_scope(|| {
+            allocator
+                .with_scope(|| registry.get_or_insert(&0u8, layout, alloc));
+        })
+    };
+    assert_eq!(used, allocator.used());
+}
+
+#[test]
+#[cfg(not(any(
+    feature = "reclaim",
+    feature = "size-classes",
+    feature = "debug-heap",
+    feature = "checked-free"
+)))]
+fn test_with_capacity() {
+    let allocator = BumpAllocator::<[u64; 4]>::with_capacity(1024);
+    let header = core::mem::size_of::<crate::imp::Header<[u64; 4]>>();
+    assert_eq!(Some(1024 - header), allocator.remaining());
+    let layout = Layout::from_size_align(1024 - header, 1).unwrap();
+    assert!(allocator.check_alloc(layout).is_some());
+    assert_eq!(Some(0), allocator.remaining());
+}
+
+#[test]
+#[cfg(feature = "allocator-api2")]
+fn test_allocator_api2_zero_size() {
+    use allocator_api2::alloc::Allocator;
+
+    let allocator = BumpAllocator::<()>::with_capacity(1024);
+    let remaining = allocator.remaining();
+    // Zero-sized blocks take no memory.  `Box` frees a dangling pointer it
+    // never allocated.
+    drop(allocator_api2::boxed::Box::new_in((), &allocator));
+    let empty = Layout::from_size_align(0, 8).unwrap();
+    let ptr = (&allocator).allocate(empty).unwrap().cast::<u8>();
+    assert_eq!(remaining, allocator.remaining());
+
+    // Growing a zero-sized block allocates and shrinking to zero frees it.
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let ptr = unsafe { (&allocator).grow(ptr, empty, layout) }.unwrap();
+    let ptr = ptr.cast::<u8>();
+    let ptr = unsafe { (&allocator).shrink(ptr, layout, empty) }.unwrap();
+    unsafe { (&allocator).deallocate(ptr.cast(), empty) };
+}
+
+#[test]
+#[cfg(feature = "allocator-api2")]
+fn test_allocator_api2() {
+    use allocator_api2::vec::Vec;
+
+    let allocator = BumpAllocator::<()>::with_capacity(1024);
+    let mut vec = Vec::<u8, _>::new_in(&allocator);
+    vec.extend_from_slice(b"foo");
+    vec.reserve_exact(100);
+    let remaining = allocator.remaining().unwrap();
+    assert!(vec.try_reserve_exact(remaining + 200).is_err());
+    vec.extend_from_slice(b"bar");
+    assert_eq!(b"foobar", vec.as_slice());
+
+    // Memory of the vector is returned once it’s dropped.
+    let used = allocator.remaining().unwrap() + vec.capacity();
+    drop(vec);
+    let remaining = allocator.remaining().unwrap();
+    assert!(remaining >= used, "{remaining} < {used}");
+
+    // Memory used by a vector which doesn’t reallocate is all available again
+    // once it’s freed.
+    let vec = Vec::<u8, _>::with_capacity_in(remaining / 2, &allocator);
+    assert!(allocator.remaining().unwrap() <= remaining - remaining / 2);
+    drop(vec);
+    assert_eq!(Some(remaining), allocator.remaining());
+}
@@ -29,13 +29,70 @@
 
 extern crate alloc;
 
-#[cfg(any(test, target_os = "solana"))]
+#[cfg(all(
+    any(test, feature = "testing", fixed_heap),
+    feature = "allocator-api2"
+))]
+mod api2;
+pub mod array;
+#[cfg(any(feature = "report", feature = "trace"))]
+mod base64;
+pub mod cell;
+#[cfg(any(test, feature = "testing", fixed_heap))]
+mod free_list;
+#[cfg(any(test, feature = "testing", fixed_heap))]
+mod heap;
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+pub mod host;
+#[cfg(any(test, feature = "testing", fixed_heap))]
 mod imp;
-#[cfg(any(test, target_os = "solana"))]
+#[cfg(all(
+    fixed_heap,
+    not(test),
+    target_os = "linux",
+    feature = "sbf-memory-map"
+))]
+pub mod mmap;
+#[cfg(any(test, feature = "testing", fixed_heap))]
 mod ptr;
+#[cfg(feature = "report")]
+pub mod report;
+pub mod slot;
+#[cfg(all(any(test, feature = "testing", fixed_heap), feature = "stats"))]
+mod stats;
+pub mod sysvar;
+#[cfg(feature = "trace")]
+pub mod trace;
 
-#[cfg(any(test, target_os = "solana"))]
-pub use imp::BumpAllocator;
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub use free_list::FreeListAllocator;
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub use heap::AllocError;
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+pub use host::{HeapExhausted, HostAllocator};
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub use imp::{BumpAllocator, Checkpoint};
+#[cfg(not(target_os = "solana"))]
+pub use slot::reset_globals;
+#[cfg(all(any(test, feature = "testing", fixed_heap), feature = "stats"))]
+pub use stats::Stats;
+
+/// Allocator used by [`custom_heap`] and [`custom_global`] macros.
+///
+/// This is [`BumpAllocator`] unless `free-list` Cargo feature is enabled in
+/// which case it’s [`FreeListAllocator`].
+#[cfg(all(
+    any(test, feature = "testing", fixed_heap),
+    not(feature = "free-list")
+))]
+pub type Allocator<G = ()> = BumpAllocator<G>;
+
+/// Allocator used by [`custom_heap`] and [`custom_global`] macros.
+///
+/// This is [`BumpAllocator`] unless `free-list` Cargo feature is enabled in
+/// which case it’s [`FreeListAllocator`].
+#[cfg(all(any(test, feature = "testing", fixed_heap), feature = "free-list"))]
+pub type Allocator<G = ()> = FreeListAllocator<G>;
 
 
 /// On Solana, defines `BumpAllocator` as the global allocator.
@@ -44,27 +101,89 @@ pub use imp::BumpAllocator;
 /// declares it as a the global allocator.  When compiling for other platforms,
 /// does nothing.
 ///
+/// If `free-list` Cargo feature is enabled, [`FreeListAllocator`] is used
+/// instead.  See [`Allocator`].
+///
 /// Note that, as always when defining custom global allocator on Solana, if
 /// using `solana_program::entrypoint` or `anchor::program` macros, the smart
 /// contract must define and enable `custom-heap` feature.  Otherwise, global
 /// allocator defined by this macro and in `solana_program` will clash.
 ///
+/// # Heap size
+///
+/// ```ignore
+/// custom_heap!(heap_size = $size);
+/// ```
+///
+/// Declares size of the heap.  Allocations which don’t fit in the heap of
+/// given size fail gracefully rather than causing an access violation.  See
+/// [`BumpAllocator::with_heap_size`].  `$size` must be a constant expression
+/// no greater than [`sysvar::MAX_HEAP_FRAME`]; larger sizes fail to compile:
+///
+/// ```compile_fail
+/// solana_allocator::custom_heap!(heap_size = 512 * 1024);
+/// ```
+///
+/// # Allocator handle
+///
+/// When compiling for Solana, the macro also defines `pub(crate) fn
+/// allocator() -> &'static Allocator<()>` function which returns the global
+/// allocator.  It can be used to call allocator’s methods such as
+/// `set_heap_size` or (with `stats` Cargo feature enabled) `stats`.
+///
 /// # Example
 ///
 /// ```ignore
 /// #[cfg(not(feature = "cpi"))]
 /// solana_allocator::custom_heap!();
+///
+/// #[cfg(all(target_os = "solana", not(feature = "cpi")))]
+/// fn log_heap_usage() {
+///     let stats = allocator().stats();
+///     solana_program::msg!("heap: {} (peak {})", stats.used, stats.peak);
+/// }
 /// ```
 #[macro_export]
 macro_rules! custom_heap {
     () => {
+        $crate::custom_heap!(heap_size = 0);
+    };
+
+    (heap_size = $size:expr) => {
+        $crate::custom_heap!(@check_size $size);
+
         #[cfg(target_os = "solana")]
         #[global_allocator]
         // SAFETY: We’re compiling for Solana and declaring this as a global
         // allocator which can exist only one.
-        static A: $crate::BumpAllocator<()> = unsafe {
-            $crate::BumpAllocator::new();
-        };
+        static A: $crate::Allocator<()> =
+            unsafe { $crate::Allocator::with_heap_size($size) };
+
+        /// Returns the global allocator.
+        #[cfg(target_os = "solana")]
+        #[allow(dead_code)]
+        pub(crate) fn allocator() -> &'static $crate::Allocator<()> { &A }
+
+        $crate::custom_heap!(@registry A);
+    };
+
+    (@check_size $size:expr) => {
+        // Heap larger than the largest heap frame would overlap the program
+        // input region.
+        const _: () = assert!(
+            $size as u64 <= $crate::sysvar::MAX_HEAP_FRAME as u64,
+            "heap_size larger than MAX_HEAP_FRAME",
+        );
+    };
+
+    (@registry $A:ident) => {
+        // Used by global slots to find their registry.  See
+        // `solana_allocator::slot` module.
+        #[cfg(target_os = "solana")]
+        #[no_mangle]
+        fn __solana_allocator_registry() -> &'static $crate::slot::Registry {
+            $A.registry()
+        }
     };
 }
 
@@ -76,7 +195,8 @@ macro_rules! custom_heap {
 /// which returns static object living in mutable memory.  The name of the
 /// function and type of the global object depend on the invocation.
 ///
-/// See also caveats in [`custom_heap`] macro.
+/// See also caveats in [`custom_heap`] macro.  Just like that macro, this uses
+/// [`FreeListAllocator`] if `free-list` Cargo feature is enabled.
 ///
 /// # Existing type
 ///
@@ -89,11 +209,15 @@ macro_rules! custom_heap {
 /// to a `'static` value of type `$Global`.  In the second invocation, the name
 /// of the function is `global`.
 ///
-/// `$Global` must be a [`Sync`](`core::marker::Sync`) and
-/// [`bytemuck::Zeroable`] type.  Furthermore, the `$name` function returns
-/// a shared reference to the static objects which doesn’t allow modification
-/// unless the type has internal mutability.  This can be achieved by
-/// [`Cell`](`core::cell::Cell`).
+/// `$Global` must be a [`bytemuck::Zeroable`] type.  Furthermore, the `$name`
+/// function returns a shared reference to the static objects which doesn’t
+/// allow modification unless the type has internal mutability.  This can be
+/// achieved by [`Cell`](`core::cell::Cell`) or [`cell::RefCell`].
+///
+/// `$Global` doesn’t need to be [`Sync`](`core::marker::Sync`).  The macro
+/// wraps it in [`cell::SolanaLocal`] which is `Sync` when building for Solana
+/// based on the observation that Solana is single-threaded thus passing data
+/// between threads is not a concern.
 ///
 /// # Global struct definition
 ///
@@ -103,23 +227,53 @@ macro_rules! custom_heap {
 /// ```
 ///
 /// Defines a struct `$Global` and uses that as the global object.  Note that
-/// all fields of the struct must be [`bytemuck::Zeroable`] *however* they do
-/// not need to be [`Sync`](`core::marker::Sync`).  When building on Solana, the
-/// macro will unsafely declare `$Global` as `Sync` based on the observation
-/// that Solana is single-threaded thus passing data between threads is not
-/// a concern.
+/// all fields of the struct must be [`bytemuck::Zeroable`] *however*, as
+/// described above, they do not need to be [`Sync`](`core::marker::Sync`).
+///
+/// # Mutable global state
+///
+/// ```ignore
+/// custom_global!(mut $visibility fn $name() -> $Global);
+/// custom_global!(mut $visibility fn $name() -> struct $Global { ... });
+/// custom_global!(mut $visibility type $Global);
+/// custom_global!(mut $visibility struct $Global { ... });
+/// ```
+///
+/// With `mut` prefix, rather than returning a shared reference, `$name`
+/// function (`with_global` by default) takes a closure and calls it with
+/// a mutable reference to the global object, i.e. it’s declared as `fn
+/// $name<R>(f: impl FnOnce(&mut $Global) -> R) -> R`.  This lets the global
+/// object be a plain structure without any `Cell` wrappers.  The function
+/// panics if called from within the closure.  See
+/// [`BumpAllocator::with_global_mut`].
+///
+/// # Heap size
+///
+/// ```ignore
+/// custom_global!(heap_size = $size, ...);
+/// ```
+///
+/// Each of the above forms can be prefixed with `heap_size = $size` argument
+/// which declares size of the heap.  See [`custom_heap`].
+///
+/// # Allocator handle
+///
+/// When compiling for Solana, the macro also defines `$visibility fn
+/// allocator() -> &'static Allocator<SolanaLocal<$Global>>` function which
+/// returns the global allocator.  See [`custom_heap`].
 ///
 /// # Non-Solana target
 ///
 /// When not building for Solana (i.e. for `not(target_os = "solana")`
 /// configuration), the macro doesn’t set the global allocator nor defines the
-/// `$name` function returning the global state.  (Note that the invocation with
-/// `struct $Global` definition defines the struct regardless of the target).
+/// `allocator` function.  The `$name` function returns global state stored in
+/// thread-local storage (see [`slot`] module) thus each thread has its own
+/// instance of the state.
 ///
-/// Caller is responsible for using appropriate conditional compilation to
-/// provide all the necessary global state.  One approach is to provide wrapper
-/// functions which use the global object when building on Solana and use static
-/// atomic type or locked variables when building on other platforms.
+/// On chain, each program invocation starts with fresh global state.  When
+/// running the program natively (e.g. with `solana-program-test`), the state
+/// persists between invocations unless [`reset_globals`] is called before
+/// each of them.
 ///
 /// # Example
 ///
@@ -130,63 +284,142 @@ macro_rules! custom_heap {
 ///         counter: Cell<usize>,
 ///     });
 ///
-///     #[cfg(all(target_os = "solana", not(feature = "cpi")))]
+///     #[cfg(not(feature = "cpi"))]
 ///     pub fn unique() -> usize {
 ///         let counter = &global().counter;
 ///         let value = counter.get();
 ///         counter.set(value + 1);
 ///         value
 ///     }
-///
-///     #[cfg(not(target_os = "solana"))]
-///     pub fn unique() -> usize {
-///         use std::sync::atomic::{AtomicUsize, Ordering};
-///         static COUNTER: AtomicUsize = AtomicUsize::new(0);
-///         COUNTER.fetch_add(1, Ordering::SeqCst)
-///     }
 /// }
 /// ```
 #[macro_export]
 macro_rules! custom_global {
+    (heap_size = $size:expr, $($tt:tt)*) => {
+        $crate::custom_global!(@impl $size, $($tt)*);
+    };
+
+    (mut $($tt:tt)*) => {
+        $crate::custom_global!(@impl 0, mut $($tt)*);
+    };
+
+    ($visibility:vis fn $name:ident() -> struct $G:ident { $($tt:tt)* }) => {
+        $crate::custom_global!(
+            @impl 0, $visibility fn $name() -> struct $G { $($tt)* }
+        );
+    };
+
+    ($visibility:vis struct $G:ident { $($tt:tt)* }) => {
+        $crate::custom_global!(@impl 0, $visibility struct $G { $($tt)* });
+    };
+
     ($visibility:vis fn $name:ident() -> $G:ty) => {
-        #[cfg(target_os = "solana")]
-        $visibility fn $name() -> &'static $G {
-            #[global_allocator]
-            // SAFETY: We’re compiling for Solana and declaring this as a global
-            // allocator which can exist only one.
-            static A: $crate::BumpAllocator<$G> = unsafe {
-                $crate::BumpAllocator::new()
-            };
-
-            A.global()
-        }
+        $crate::custom_global!(@impl 0, $visibility fn $name() -> $G);
     };
 
     ($visibility:vis type $G:ty) => {
-        $crate::custom_global!($visibility fn global() -> $G);
+        $crate::custom_global!(@impl 0, $visibility type $G);
     };
 
-    ($visibility:vis fn $name:ident() -> struct $G:ident { $($tt:tt)* }) => {
+    (@impl $size:expr, mut $visibility:vis fn $name:ident() -> struct $G:ident {
+        $($tt:tt)*
+    }) => {
         #[derive(bytemuck::Zeroable)]
         $visibility struct $G { $($tt)* }
 
-        // SAFETY: $G might not be Sync.  However, Solana is single-threaded so
-        // we don’t need to worry about thread safety.  Since this
-        // implementation is used when building for Solana, we can safely lie to
-        // the compiler about $G being Sync.
-        //
-        // We need $G to be Sync because it’s !Sync status percolates to
-        // BumpAllocator<$G> and since that’s a static variable, Rust requires
-        // that it’s Sync.
+        $crate::custom_global!(@impl $size, mut $visibility fn $name() -> $G);
+    };
+
+    (@impl $size:expr, mut $visibility:vis struct $G:ident { $($tt:tt)* }) => {
+        $crate::custom_global!(
+            @impl $size, mut $visibility fn with_global() -> struct $G {
+                $($tt)*
+            }
+        );
+    };
+
+    (@impl $size:expr, mut $visibility:vis fn $name:ident() -> $G:ty) => {
+        $crate::custom_global!(@allocator $size, $visibility $G);
+
         #[cfg(target_os = "solana")]
-        unsafe impl core::marker::Sync for $G {}
+        #[track_caller]
+        $visibility fn $name<R>(f: impl FnOnce(&mut $G) -> R) -> R {
+            A.with_global_mut(|global| f(&mut **global))
+        }
 
-        $crate::custom_global!($visibility fn $name() -> $G);
+        #[cfg(not(target_os = "solana"))]
+        #[track_caller]
+        $visibility fn $name<R>(f: impl FnOnce(&mut $G) -> R) -> R {
+            static SLOT: $crate::slot::GlobalSlot<
+                $crate::cell::GlobalCell<$G>,
+            > = $crate::slot::GlobalSlot::new();
+            SLOT.get()
+                .try_with_mut(f)
+                .expect("global state already borrowed")
+        }
     };
 
-    ($visibility:vis struct $G:ident { $($tt:tt)* }) => {
+    (@impl $size:expr, mut $visibility:vis type $G:ty) => {
         $crate::custom_global!(
-            $visibility fn global() -> struct $G { $($tt)* }
+            @impl $size, mut $visibility fn with_global() -> $G
         );
-    }
+    };
+
+    (@impl $size:expr, $visibility:vis fn $name:ident() -> struct $G:ident {
+        $($tt:tt)*
+    }) => {
+        #[derive(bytemuck::Zeroable)]
+        $visibility struct $G { $($tt)* }
+
+        $crate::custom_global!(@impl $size, $visibility fn $name() -> $G);
+    };
+
+    (@impl $size:expr, $visibility:vis struct $G:ident { $($tt:tt)* }) => {
+        $crate::custom_global!(
+            @impl $size, $visibility fn global() -> struct $G { $($tt)* }
+        );
+    };
+
+    (@impl $size:expr, $visibility:vis fn $name:ident() -> $G:ty) => {
+        $crate::custom_global!(@allocator $size, $visibility $G);
+
+        #[cfg(target_os = "solana")]
+        $visibility fn $name() -> &'static $G { &**A.global() }
+
+        #[cfg(not(target_os = "solana"))]
+        $visibility fn $name() -> &'static $G {
+            static SLOT: $crate::slot::GlobalSlot<$G> =
+                $crate::slot::GlobalSlot::new();
+            SLOT.get()
+        }
+    };
+
+    (@impl $size:expr, $visibility:vis type $G:ty) => {
+        $crate::custom_global!(@impl $size, $visibility fn global() -> $G);
+    };
+
+    (@allocator $size:expr, $visibility:vis $G:ty) => {
+        $crate::custom_heap!(@check_size $size);
+
+        #[cfg(target_os = "solana")]
+        #[global_allocator]
+        // SAFETY: We’re compiling for Solana and declaring this as a global
+        // allocator which can exist only one.
+        //
+        // $G might not be Sync.  SolanaLocal wrapper makes it Sync when
+        // building for Solana which is required for a static variable.
+        static A: $crate::Allocator<$crate::cell::SolanaLocal<$G>> = unsafe {
+            $crate::Allocator::with_heap_size($size)
+        };
+
+        /// Returns the global allocator.
+        #[cfg(target_os = "solana")]
+        #[allow(dead_code)]
+        $visibility fn allocator(
+        ) -> &'static $crate::Allocator<$crate::cell::SolanaLocal<$G>> {
+            &A
+        }
+
+        $crate::custom_heap!(@registry A);
+    };
 }
@@ -0,0 +1,68 @@
+//! Reproduction of Solana memory map on Linux.
+//!
+//! With `sbf-memory-map` Cargo feature enabled, allocators use the same fixed
+//! heap address as on Solana.  Before they can be used, the heap has to be
+//! mapped with [`map_heap`].  Memory above the heap is left unmapped so
+//! accessing it results in a segmentation fault just like it results in an
+//! access violation on Solana.
+//!
+//! # Example
+//!
+//! ```ignore
+//! solana_allocator::mmap::map_heap(32 * 1024).unwrap();
+//! // SAFETY: This is the only allocator using the heap.
+//! let allocator = unsafe { solana_allocator::BumpAllocator::<()>::new() };
+//! let ptr = unsafe { allocator.alloc(Layout::new::<u64>()) };
+//! assert_eq!(0x3_0000_0000, ptr as usize & !0xffff);
+//! ```
+
+use std::io;
+
+use crate::heap::HEAP_START_ADDRESS;
+
+/// Maps zero-initialised heap of given size at the address of Solana heap.
+///
+/// Fails if any part of the region is already mapped.  Note that heap size
+/// declared with allocator’s `with_heap_size` or `set_heap_size` should match
+/// `size` for out-of-memory conditions to be reported gracefully.
+pub fn map_heap(size: usize) -> io::Result<()> {
+    let addr = HEAP_START_ADDRESS as *mut libc::c_void;
+    // SAFETY: MAP_FIXED_NOREPLACE never replaces existing mappings.
+    let ptr = unsafe {
+        libc::mmap(
+            addr,
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    if ptr != addr {
+        // Kernels older than 4.17 treat unknown MAP_FIXED_NOREPLACE flag as
+        // a hint and may map the region elsewhere.
+        // SAFETY: We’ve just mapped the region.
+        unsafe { libc::munmap(ptr, size) };
+        return Err(io::Error::from_raw_os_error(libc::EEXIST));
+    }
+    Ok(())
+}
+
+/// Unmaps heap mapped with [`map_heap`].
+///
+/// # Safety
+///
+/// `size` must be the same as passed to [`map_heap`].  Any memory allocated
+/// on the heap must not be used afterwards and allocators mustn’t be used
+/// until the heap is mapped again.
+pub unsafe fn unmap_heap(size: usize) -> io::Result<()> {
+    let addr = HEAP_START_ADDRESS as *mut libc::c_void;
+    // SAFETY: Caller guarantees the region is our mapping.
+    if unsafe { libc::munmap(addr, size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
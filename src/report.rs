@@ -0,0 +1,399 @@
+//! Reporting of heap usage of program’s instructions.
+//!
+//! Solana programs cannot easily tell how much heap their instructions need
+//! which makes choosing size for Compute Budget program’s `RequestHeapFrame`
+//! instruction guesswork.  This module offers a wrapper which measures heap
+//! usage of a function (typically program’s process instruction function) and
+//! emits a single machine-parseable line with the results.  Clients can then
+//! simulate transactions and read real heap requirements from the logs.
+//!
+//! The line has the following format (with all numbers in bytes):
+//!
+//! ```text
+//! solana-allocator: heap-usage before=<n> after=<n> peak=<n>
+//! ```
+//!
+//! Since the bump allocator cannot reuse memory freed out of order, temporary
+//! objects which outlive objects allocated after them eat up the heap.
+//! [`Leaks`] summarises allocations which are still live and memory which has
+//! been freed but not reclaimed.  It’s meant to be logged at the end of the
+//! entrypoint:
+//!
+//! ```text
+//! solana-allocator: heap-leaks live=<n> live-bytes=<n> unreclaimed=<n>
+//! ```
+//!
+//! # Example
+//!
+//! ```ignore
+//! solana_allocator::custom_heap!();
+//!
+//! solana_program::entrypoint!(process_instruction);
+//!
+//! fn process_instruction(
+//!     program_id: &Pubkey,
+//!     accounts: &[AccountInfo],
+//!     data: &[u8],
+//! ) -> ProgramResult {
+//!     let result = solana_allocator::report::instrument(
+//!         allocator(),
+//!         solana_allocator::report::Output::Log,
+//!         || process(program_id, accounts, data),
+//!     );
+//!     solana_allocator::report::leaks(allocator()).log();
+//!     result
+//! }
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(test)]
+mod tests;
+
+/// Prefix of the line emitted by [`Report::log`] and
+/// [`Report::set_return_data`].
+pub const PREFIX: &str = "solana-allocator: heap-usage";
+
+/// Prefix of the line emitted by [`Leaks::log`].
+pub const LEAKS_PREFIX: &str = "solana-allocator: heap-leaks";
+
+/// Heap usage of an instruction.
+///
+/// All values are offsets from the start of the heap, i.e. they include space
+/// reserved for allocator’s internal data and global state.  This makes them
+/// directly comparable with heap frame size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Heap usage before the instruction was executed.
+    pub before: usize,
+    /// Heap usage after the instruction finished.
+    pub after: usize,
+    /// Peak heap usage while the instruction was executing.
+    pub peak: usize,
+}
+
+/// Where [`instrument`] emits the report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// Log the report with `sol_log`.  See [`Report::log`].
+    Log,
+    /// Set the report as transaction return data.  See
+    /// [`Report::set_return_data`].
+    ReturnData,
+}
+
+impl Report {
+    /// Parses a line in the format produced by [`Report`]’s `Display`
+    /// implementation.
+    ///
+    /// The line may be preceded by arbitrary text (such as `Program log: `
+    /// prefix added by Solana runtime to logged messages).  Returns `None` if
+    /// the line doesn’t contain a report.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut next = fields(line, PREFIX)?;
+        let before = next("before")?;
+        let after = next("after")?;
+        let peak = next("peak")?;
+        Some(Self { before, after, peak })
+    }
+
+    /// Logs the report with `sol_log` syscall.
+    ///
+    /// The line is formatted on the stack so logging doesn’t allocate memory.
+    /// When not building for Solana, the line is printed to standard error.
+    pub fn log(&self) { Buffer::format(self).log() }
+
+    /// Sets the report as transaction return data.
+    ///
+    /// This overrides any return data set by the program.  When not building
+    /// for Solana, this does nothing.
+    pub fn set_return_data(&self) {
+        let line = Buffer::format(self);
+        #[cfg(target_os = "solana")]
+        // SAFETY: Pointer and length describe a valid buffer.
+        unsafe {
+            sol_set_return_data(
+                line.as_bytes().as_ptr(),
+                line.as_bytes().len() as u64,
+            )
+        };
+        #[cfg(not(target_os = "solana"))]
+        let _ = line;
+    }
+
+    /// Emits the report to given output.
+    pub fn emit(&self, output: Output) {
+        match output {
+            Output::Log => self.log(),
+            Output::ReturnData => self.set_return_data(),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmtr,
+            "{PREFIX} before={} after={} peak={}",
+            self.before, self.after, self.peak
+        )
+    }
+}
+
+
+/// Summary of allocations which haven’t been freed.
+///
+/// Obtained with `leaks` function or converted from allocator’s statistics.
+/// Host tests can assert on the fields directly, e.g. that
+/// no allocations outlive an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Leaks {
+    /// Number of allocations which haven’t been freed.
+    ///
+    /// This includes global slots (see [`crate::slot`]) which are allocated on
+    /// first access and never freed.
+    pub live: usize,
+    /// Number of bytes requested by allocations which haven’t been freed.
+    pub live_bytes: usize,
+    /// Number of bytes of the heap in use which don’t hold live allocations.
+    ///
+    /// This is mostly memory of objects which have been freed out of order.
+    /// It also includes per-block metadata and alignment padding.
+    pub unreclaimed: usize,
+}
+
+impl Leaks {
+    /// Parses a line in the format produced by [`Leaks`]’s `Display`
+    /// implementation.
+    ///
+    /// Like [`Report::parse`], the line may be preceded by arbitrary text.
+    /// Returns `None` if the line doesn’t contain a leak summary.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut next = fields(line, LEAKS_PREFIX)?;
+        let live = next("live")?;
+        let live_bytes = next("live-bytes")?;
+        let unreclaimed = next("unreclaimed")?;
+        Some(Self { live, live_bytes, unreclaimed })
+    }
+
+    /// Logs the summary with `sol_log` syscall.
+    ///
+    /// The line is formatted on the stack so logging doesn’t allocate memory.
+    /// When not building for Solana, the line is printed to standard error.
+    pub fn log(&self) { Buffer::format(self).log() }
+}
+
+#[cfg(any(test, feature = "testing", fixed_heap))]
+impl From<crate::Stats> for Leaks {
+    fn from(stats: crate::Stats) -> Self {
+        Self {
+            live: stats.live(),
+            live_bytes: stats.live_bytes,
+            unreclaimed: stats.unreclaimed(),
+        }
+    }
+}
+
+impl fmt::Display for Leaks {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmtr,
+            "{LEAKS_PREFIX} live={} live-bytes={} unreclaimed={}",
+            self.live, self.live_bytes, self.unreclaimed
+        )
+    }
+}
+
+
+/// Returns iterator-like closure over `name=value` fields of a line following
+/// given prefix or `None` if the line doesn’t contain the prefix.
+///
+/// Each call of the closure parses the next field if it has given name.
+fn fields<'a>(
+    line: &'a str,
+    prefix: &str,
+) -> Option<impl FnMut(&str) -> Option<usize> + 'a> {
+    let (_, rest) = line.split_once(prefix)?;
+    let mut fields = rest.split_whitespace();
+    Some(move |name: &str| {
+        fields.next()?.strip_prefix(name)?.strip_prefix('=')?.parse().ok()
+    })
+}
+
+
+/// Heap usage report found in transaction logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramReport {
+    /// Program which emitted the report or `None` if unknown.
+    pub program: Option<String>,
+    pub report: Report,
+}
+
+/// Extracts heap usage reports from transaction logs.
+///
+/// Reports are found in logged lines (see [`Report::log`]) and in `Program
+/// return: <id> <data>` lines (see [`Report::set_return_data`]).  Keeps track
+/// of `Program <id> invoke [<depth>]` and `Program <id> success`/`failed`
+/// lines to attribute each logged report to the program which emitted it.
+pub fn from_logs<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+) -> Vec<ProgramReport> {
+    use alloc::string::ToString;
+
+    let mut stack = Vec::<&str>::new();
+    let mut reports = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if let Some(report) = Report::parse(line) {
+            let program = stack.last().map(|id| id.to_string());
+            reports.push(ProgramReport { program, report });
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (Some("Program"), Some(id), Some(what)) =
+            (words.next(), words.next(), words.next())
+        else {
+            continue;
+        };
+        if id == "return:" {
+            let report = words
+                .next()
+                .and_then(crate::base64::decode)
+                .and_then(|data| String::from_utf8(data).ok())
+                .and_then(|data| Report::parse(&data));
+            if let Some(report) = report {
+                let program = Some(what.to_string());
+                reports.push(ProgramReport { program, report });
+            }
+        } else if what == "invoke" {
+            stack.push(id);
+        } else if (what == "success" || what == "failed:") &&
+            stack.last() == Some(&id)
+        {
+            stack.pop();
+        }
+    }
+    reports
+}
+
+/// Returns smallest heap frame size which can be requested with
+/// `RequestHeapFrame` instruction and which fits `peak` bytes.
+///
+/// The size is rounded up to multiple of
+/// [`HEAP_FRAME_GRANULARITY`](`crate::sysvar::HEAP_FRAME_GRANULARITY`) and is
+/// at least [`DEFAULT_HEAP_FRAME`](`crate::sysvar::DEFAULT_HEAP_FRAME`).
+/// Returns `None` if the size would exceed
+/// [`MAX_HEAP_FRAME`](`crate::sysvar::MAX_HEAP_FRAME`).
+pub fn heap_frame(peak: usize) -> Option<u32> {
+    use crate::sysvar::{
+        DEFAULT_HEAP_FRAME, HEAP_FRAME_GRANULARITY, MAX_HEAP_FRAME,
+    };
+    let size = u32::try_from(peak).ok()?.max(DEFAULT_HEAP_FRAME);
+    let size = size.checked_next_multiple_of(HEAP_FRAME_GRANULARITY)?;
+    Some(size).filter(|&size| size <= MAX_HEAP_FRAME)
+}
+
+/// Executes `f` and returns its result together with its heap usage.
+///
+/// Resets allocator’s peak usage before executing `f`.
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub fn measure<G: bytemuck::Zeroable, R>(
+    allocator: &crate::Allocator<G>,
+    f: impl FnOnce() -> R,
+) -> (R, Report) {
+    allocator.reset_peak();
+    let before = allocator.stats();
+    let result = f();
+    let after = allocator.stats();
+    let report = Report {
+        before: before.reserved + before.used,
+        after: after.reserved + after.used,
+        peak: after.reserved + after.peak,
+    };
+    (result, report)
+}
+
+/// Executes `f` and emits report of its heap usage to given output.
+///
+/// This is meant to wrap program’s process instruction function.  See
+/// [module documentation](`self`) for an example.
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub fn instrument<G: bytemuck::Zeroable, R>(
+    allocator: &crate::Allocator<G>,
+    output: Output,
+    f: impl FnOnce() -> R,
+) -> R {
+    let (result, report) = measure(allocator, f);
+    report.emit(output);
+    result
+}
+
+/// Returns summary of allocator’s allocations which haven’t been freed.
+///
+/// This is meant to be called at the end of program’s entrypoint with the
+/// result logged with [`Leaks::log`].
+#[cfg(any(test, feature = "testing", fixed_heap))]
+pub fn leaks<G: bytemuck::Zeroable>(allocator: &crate::Allocator<G>) -> Leaks {
+    Leaks::from(allocator.stats())
+}
+
+
+/// Fixed-size buffer the report is formatted into.
+struct Buffer {
+    bytes: [u8; Self::CAPACITY],
+    len: usize,
+}
+
+impl Buffer {
+    /// Length of the longest line, i.e. leak summary with three 20-digit
+    /// numbers.
+    const CAPACITY: usize =
+        LEAKS_PREFIX.len() + " live= live-bytes= unreclaimed=".len() + 60;
+
+    /// Formats a line into a stack buffer.
+    fn format(line: &impl fmt::Display) -> Self {
+        let mut buf = Self { bytes: [0; Self::CAPACITY], len: 0 };
+        // Buffer is large enough to hold the longest possible line.
+        fmt::Write::write_fmt(&mut buf, format_args!("{line}")).unwrap();
+        buf
+    }
+
+    /// Logs the line with `sol_log` syscall or prints it to standard error
+    /// when not building for Solana.
+    fn log(&self) {
+        #[cfg(target_os = "solana")]
+        // SAFETY: Pointer and length describe a valid buffer.
+        unsafe {
+            sol_log_(self.as_bytes().as_ptr(), self.as_bytes().len() as u64)
+        };
+        #[cfg(not(target_os = "solana"))]
+        std::eprintln!("{}", self.as_str());
+    }
+
+    fn as_bytes(&self) -> &[u8] { &self.bytes[..self.len] }
+
+    #[cfg(not(target_os = "solana"))]
+    fn as_str(&self) -> &str {
+        // Only complete strings are ever written to the buffer.
+        core::str::from_utf8(self.as_bytes()).unwrap()
+    }
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let dst = self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?;
+        dst.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+
+#[cfg(target_os = "solana")]
+extern "C" {
+    fn sol_log_(message: *const u8, len: u64);
+    fn sol_set_return_data(data: *const u8, len: u64);
+}
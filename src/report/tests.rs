@@ -0,0 +1,163 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+
+use super::*;
+
+#[test]
+fn test_format_parse() {
+    let report = Report { before: 128, after: 256, peak: 4096 };
+    let line = report.to_string();
+    assert_eq!(
+        "solana-allocator: heap-usage before=128 after=256 peak=4096",
+        line
+    );
+    assert_eq!(Some(report), Report::parse(&line));
+    let line = std::format!("Program log: {line}");
+    assert_eq!(Some(report), Report::parse(&line));
+
+    let report = Report { before: usize::MAX, after: 0, peak: usize::MAX };
+    assert_eq!(
+        report.to_string().as_bytes(),
+        Buffer::format(&report).as_bytes()
+    );
+
+    assert_eq!(None, Report::parse("Program log: hello"));
+    assert_eq!(None, Report::parse("solana-allocator: heap-usage before=1"));
+    assert_eq!(
+        None,
+        Report::parse("solana-allocator: heap-usage before=1 peak=2 after=3")
+    );
+}
+
+#[test]
+fn test_measure() {
+    let allocator = crate::Allocator::<()>::new(1024);
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+
+    let ((), report) = measure(&allocator, || unsafe {
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+    });
+    assert!(report.before > 200);
+    assert_eq!(report.before, report.after);
+    assert!(report.peak >= report.before + 200);
+
+    // Peak is reset on each measurement.
+    unsafe { allocator.dealloc(first, layout) };
+    let (ptr, second) =
+        measure(&allocator, || unsafe { allocator.alloc(layout) });
+    assert!(second.before < report.before);
+    assert_eq!(second.after, second.peak);
+    assert!(second.peak < report.peak);
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+fn test_leaks_format_parse() {
+    let leaks = Leaks { live: 3, live_bytes: 96, unreclaimed: 1024 };
+    let line = leaks.to_string();
+    assert_eq!(
+        "solana-allocator: heap-leaks live=3 live-bytes=96 unreclaimed=1024",
+        line
+    );
+    assert_eq!(Some(leaks), Leaks::parse(&line));
+    let line = std::format!("Program log: {line}");
+    assert_eq!(Some(leaks), Leaks::parse(&line));
+
+    let leaks =
+        Leaks { live: usize::MAX, live_bytes: usize::MAX, unreclaimed: 0 };
+    assert_eq!(leaks.to_string().as_bytes(), Buffer::format(&leaks).as_bytes());
+
+    // Reports and leak summaries aren’t mistaken for each other.
+    assert_eq!(None, Report::parse(&line));
+    assert_eq!(
+        None,
+        Leaks::parse("solana-allocator: heap-usage before=1 after=2 peak=3")
+    );
+    assert_eq!(
+        None,
+        Leaks::parse("solana-allocator: heap-leaks live=1 unreclaimed=2")
+    );
+}
+
+#[test]
+fn test_leaks() {
+    let allocator = crate::Allocator::<()>::new(1024);
+    assert_eq!(
+        Leaks { live: 0, live_bytes: 0, unreclaimed: 0 },
+        leaks(&allocator)
+    );
+
+    let first = Layout::from_size_align(100, 8).unwrap();
+    let second = Layout::from_size_align(200, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(first) };
+    let other = unsafe { allocator.alloc(second) };
+    let before = leaks(&allocator);
+    assert_eq!((2, 300), (before.live, before.live_bytes));
+
+    // Memory of objects freed out of order stays in use.
+    unsafe { allocator.dealloc(ptr, first) };
+    let summary = leaks(&allocator);
+    assert_eq!((1, 200), (summary.live, summary.live_bytes));
+    assert!(summary.unreclaimed >= before.unreclaimed + 100);
+    summary.log();
+
+    unsafe { allocator.dealloc(other, second) };
+    let summary = leaks(&allocator);
+    assert_eq!((0, 0), (summary.live, summary.live_bytes));
+}
+
+#[test]
+fn test_heap_frame() {
+    assert_eq!(Some(32 * 1024), heap_frame(0));
+    assert_eq!(Some(32 * 1024), heap_frame(32 * 1024));
+    assert_eq!(Some(33 * 1024), heap_frame(32 * 1024 + 1));
+    assert_eq!(Some(256 * 1024), heap_frame(256 * 1024));
+    assert_eq!(None, heap_frame(256 * 1024 + 1));
+    assert_eq!(None, heap_frame(usize::MAX));
+}
+
+#[test]
+fn test_from_logs() {
+    let report = |before, after, peak| Report { before, after, peak };
+    let entry = |program: Option<&str>, report| ProgramReport {
+        program: program.map(alloc::string::ToString::to_string),
+        report,
+    };
+    // `solana-allocator: heap-usage before=1 after=2 peak=3` in base64.
+    let data = concat!(
+        "c29sYW5hLWFsbG9jYXRvcjogaGVhcC11c2FnZSBiZWZvcmU9MSBhZnRlcj0y",
+        "IHBlYWs9Mw==",
+    );
+    let logs = std::format!(
+        "\
+Program log: solana-allocator: heap-usage before=0 after=0 peak=1
+Program Outer111 invoke [1]
+Program log: Instruction: Run
+Program Inner111 invoke [2]
+Program log: solana-allocator: heap-usage before=8 after=8 peak=64
+Program Inner111 consumed 1000 of 190000 compute units
+Program Inner111 success
+Program log: solana-allocator: heap-usage before=16 after=32 peak=128
+Program Failing1 invoke [2]
+Program log: solana-allocator: heap-usage before=4 after=4 peak=256
+Program Failing1 failed: custom program error: 0x1
+  Program log: solana-allocator: heap-usage before=32 after=32 peak=512
+Program Outer111 consumed 5000 of 200000 compute units
+Program return: Outer111 {data}
+Program return: Outer111 aGVsbG8=
+Program Outer111 success
+Program log: solana-allocator: heap-usage before=2 after=2 peak=2
+"
+    );
+    let want = [
+        entry(None, report(0, 0, 1)),
+        entry(Some("Inner111"), report(8, 8, 64)),
+        entry(Some("Outer111"), report(16, 32, 128)),
+        entry(Some("Failing1"), report(4, 4, 256)),
+        entry(Some("Outer111"), report(32, 32, 512)),
+        entry(Some("Outer111"), report(1, 2, 3)),
+        entry(None, report(2, 2, 2)),
+    ];
+    assert_eq!(&want[..], &from_logs(logs.lines())[..]);
+}
@@ -0,0 +1,376 @@
+//! Mutable global slots which can be declared by library crates.
+//!
+//! [`custom_global`](`crate::custom_global`) macro lets the program declare
+//! a single global state object.  Libraries the program depends on can’t add
+//! their own state to it.  Global slots solve that problem.  Each slot is
+//! a `'static` object declared with [`global_slot`](`crate::global_slot`)
+//! macro which lazily reserves zero-initialised space on the heap on first
+//! access.  Reserved regions are kept on a list stored in allocator’s header
+//! keyed by the address of the slot.
+//!
+//! [`solana_static`](`crate::solana_static`) macro builds on top of that and
+//! allows declaring global objects with arbitrary initialiser (rather than
+//! starting zeroed) which is executed on first access.
+//!
+//! When building for Solana, the list is found through a function defined by
+//! [`custom_heap`](`crate::custom_heap`) and
+//! [`custom_global`](`crate::custom_global`) macros.  Using global slots in
+//! a program which doesn’t use either of those macros results in a link
+//! error.  When not building for Solana, each thread has its own set of slots
+//! allocated with the global allocator.
+//!
+//! When not building for Solana, [`custom_global`](`crate::custom_global`)
+//! macro stores the global state in a slot as well.  On chain, each program
+//! invocation starts with a fresh heap.  To emulate that when running
+//! a program natively (e.g. with `solana-program-test`), call
+//! [`reset_globals`] before each invocation.
+
+use alloc::alloc::Layout;
+use core::cell::{Cell, UnsafeCell};
+use core::mem::MaybeUninit;
+
+#[cfg(test)]
+mod tests;
+
+/// Mutable global object which can be declared by library crates.
+///
+/// Use [`global_slot`](`crate::global_slot`) macro to declare a slot.  See
+/// [module documentation](`self`) for details.
+pub struct GlobalSlot<T> {
+    /// Makes the type non-zero-sized so that each slot has a unique address.
+    _key: u8,
+    _ph: core::marker::PhantomData<fn() -> T>,
+}
+
+/// Mutable global object with lazy initialiser.
+///
+/// Use [`solana_static`](`crate::solana_static`) macro to declare the object.
+/// See [module documentation](`self`) for details.
+pub struct SolanaStatic<T> {
+    init: fn() -> T,
+}
+
+/// Storage of [`SolanaStatic`] value.  Valid when zeroed.
+struct Lazy<T> {
+    state: Cell<u8>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// [`Lazy::state`] of a value which hasn’t been initialised yet.
+const UNINIT: u8 = 0;
+/// [`Lazy::state`] of a value whose initialiser is running.
+const BUSY: u8 = 1;
+/// [`Lazy::state`] of an initialised value.
+const READY: u8 = 2;
+
+/// List of global slots.
+///
+/// This is an implementation detail used by the macros.
+#[doc(hidden)]
+pub struct Registry {
+    head: Cell<*mut Node>,
+}
+
+// SAFETY: The only field is a pointer which is null when zeroed.
+unsafe impl bytemuck::Zeroable for Registry {}
+
+/// Registered slot.  The slot’s value follows the node.
+struct Node {
+    key: *const u8,
+    next: *mut Node,
+}
+
+
+impl<T: bytemuck::Zeroable> GlobalSlot<T> {
+    /// Creates a new slot.  Use [`global_slot`](`crate::global_slot`) macro
+    /// rather than calling this directly.
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        Self { _key: 0, _ph: core::marker::PhantomData }
+    }
+
+    /// Returns reference to the slot’s value.
+    ///
+    /// On first access, allocates zero-initialised value on the heap.  Panics
+    /// (through [`alloc::alloc::handle_alloc_error`]) if the allocation fails.
+    ///
+    /// Note that a slot first accessed after a
+    /// [`BumpAllocator::checkpoint`](`crate::BumpAllocator::checkpoint`) is
+    /// reset by the corresponding rollback.
+    pub fn get(&'static self) -> &'static T {
+        let ptr = reserve(core::ptr::from_ref(self).cast(), Layout::new::<T>());
+        // SAFETY: The region is reserved for the slot with `self` as key, is
+        // aligned and sized for T and has been zero-initialised.
+        unsafe { &*ptr.cast::<T>() }
+    }
+}
+
+impl<T> SolanaStatic<T> {
+    /// Creates a new object with given initialiser.  Use
+    /// [`solana_static`](`crate::solana_static`) macro rather than calling
+    /// this directly.
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self { Self { init } }
+
+    /// Returns reference to the object’s value.
+    ///
+    /// On first access, reserves space for the value on the heap and
+    /// initialises it.  Panics if the initialiser accesses the object it
+    /// initialises.  See also [`GlobalSlot::get`].
+    pub fn get(&'static self) -> &'static T {
+        let ptr =
+            reserve(core::ptr::from_ref(self).cast(), Layout::new::<Lazy<T>>());
+        // SAFETY: The region is reserved for the slot with `self` as key, is
+        // aligned and sized for Lazy<T> and has been zero-initialised which
+        // is a valid Lazy<T>.
+        let lazy = unsafe { &*ptr.cast::<Lazy<T>>() };
+        match lazy.state.get() {
+            READY => (),
+            UNINIT => {
+                lazy.state.set(BUSY);
+                let value = (self.init)();
+                // SAFETY: The value is uninitialised so there are no
+                // references to it.
+                unsafe { (*lazy.value.get()).write(value) };
+                lazy.state.set(READY);
+            }
+            _ => panic!("solana_static accessed during its initialisation"),
+        }
+        // SAFETY: The value is initialised and is never modified again.
+        unsafe { (*lazy.value.get()).assume_init_ref() }
+    }
+
+    /// Calls `f` with reference to the object’s value.
+    ///
+    /// This is equivalent to `f(self.get())` and is provided for
+    /// compatibility with [`std::thread::LocalKey::with`].
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        f(self.get())
+    }
+}
+
+impl Registry {
+    #[cfg(not(target_os = "solana"))]
+    const fn new() -> Self { Self { head: Cell::new(core::ptr::null_mut()) } }
+
+    /// Returns head of the list.  Used to restore registry on rollback.
+    #[cfg(any(test, feature = "testing", fixed_heap))]
+    pub(crate) fn head(&self) -> *mut u8 { self.head.get().cast() }
+
+    /// Removes slots registered since `head` was returned by [`Self::head`].
+    /// Returns number of removed slots.
+    ///
+    /// # Safety
+    ///
+    /// `head` must have been returned by [`Self::head`] of this registry.
+    #[cfg(any(test, feature = "testing", fixed_heap))]
+    pub(crate) unsafe fn truncate(&self, head: *mut u8) -> usize {
+        let head = head.cast::<Node>();
+        let mut count = 0;
+        let mut it = self.head.get();
+        while it != head {
+            count += 1;
+            // SAFETY: All nodes on the list are valid and, since slots are
+            // only ever prepended, `head` is on the list.
+            it = unsafe { (*it).next };
+        }
+        self.head.set(head);
+        count
+    }
+
+    /// Returns pointer to region reserved for slot with given key.  Allocates
+    /// the region with `alloc` if the slot hasn’t been registered yet.
+    ///
+    /// `alloc` must return zero-initialised memory and `layout` must be the
+    /// same each time given key is used.
+    pub(crate) fn get_or_insert(
+        &self,
+        key: *const u8,
+        layout: Layout,
+        alloc: impl FnOnce(Layout) -> *mut u8,
+    ) -> *mut u8 {
+        let (node_layout, offset) = Layout::new::<Node>()
+            .extend(layout)
+            .unwrap_or_else(|_| panic!("Global slot too large"));
+        let mut it = self.head.get();
+        // SAFETY: All nodes on the list are valid.
+        while let Some(node) = unsafe { it.as_ref() } {
+            if node.key == key {
+                return it.cast::<u8>().wrapping_add(offset);
+            }
+            it = node.next;
+        }
+
+        let ptr = alloc(node_layout);
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(node_layout);
+        }
+        let node = Node { key, next: self.head.get() };
+        // SAFETY: ptr is aligned and sized for node_layout.
+        unsafe { ptr.cast::<Node>().write(node) };
+        self.head.set(ptr.cast());
+        ptr.wrapping_add(offset)
+    }
+}
+
+
+/// Returns pointer to zero-initialised region reserved for slot with given
+/// key.  See [`Registry::get_or_insert`].
+fn reserve(key: *const u8, layout: Layout) -> *mut u8 {
+    with_registry(|registry| {
+        registry.get_or_insert(key, layout, |layout| {
+            // SAFETY: Layout has non-zero size since it includes Node.
+            unsafe { alloc::alloc::alloc_zeroed(layout) }
+        })
+    })
+}
+
+/// Calls `f` with the registry of global slots.
+#[cfg(target_os = "solana")]
+fn with_registry<R>(f: impl FnOnce(&Registry) -> R) -> R {
+    extern "Rust" {
+        /// Defined by `custom_heap` and `custom_global` macros.
+        fn __solana_allocator_registry() -> &'static Registry;
+    }
+    // SAFETY: The function is defined by our macros.
+    f(unsafe { __solana_allocator_registry() })
+}
+
+/// Calls `f` with the registry of global slots.
+#[cfg(not(target_os = "solana"))]
+fn with_registry<R>(f: impl FnOnce(&Registry) -> R) -> R {
+    std::thread_local! {
+        static REGISTRY: Registry = const { Registry::new() };
+    }
+    REGISTRY.with(f)
+}
+
+/// Resets all global state of the current thread.
+///
+/// Resets global state declared with [`custom_global`](`crate::custom_global`),
+/// [`global_slot`](`crate::global_slot`) and
+/// [`solana_static`](`crate::solana_static`) macros such that the next access
+/// observes a freshly initialised value.  This emulates the fresh heap each
+/// program invocation starts with on chain and is meant to be called by
+/// a processor wrapper before each invocation when running the program
+/// natively.
+///
+/// References to the old values remain valid.  Since they are `'static`, the
+/// memory they occupy is leaked rather than freed.
+///
+/// This function is available only when not building for Solana.
+///
+/// # Example
+///
+/// ```ignore
+/// fn process_instruction(
+///     program_id: &Pubkey,
+///     accounts: &[AccountInfo],
+///     data: &[u8],
+/// ) -> ProgramResult {
+///     solana_allocator::reset_globals();
+///     my_program::process_instruction(program_id, accounts, data)
+/// }
+///
+/// let program_test = ProgramTest::new(
+///     "my_program",
+///     my_program::ID,
+///     processor!(process_instruction),
+/// );
+/// ```
+#[cfg(not(target_os = "solana"))]
+pub fn reset_globals() {
+    with_registry(|registry| registry.head.set(core::ptr::null_mut()))
+}
+
+
+/// Declares a mutable global object which can be used by library crates.
+///
+/// ```ignore
+/// global_slot!($visibility static $NAME: $Type);
+/// ```
+///
+/// Defines a `static $NAME: GlobalSlot<$Type>` whose
+/// [`get`](`GlobalSlot::get`) method returns a `&'static $Type` reference.
+/// `$Type` must be [`bytemuck::Zeroable`] and the value starts zeroed.  Just
+/// like with [`custom_global`](`crate::custom_global`), modifying the value
+/// requires interior mutability, e.g. [`Cell`](`core::cell::Cell`).
+///
+/// When building for Solana, the program must set the global allocator with
+/// [`custom_heap`](`crate::custom_heap`) or
+/// [`custom_global`](`crate::custom_global`) macro.  When not building for
+/// Solana, each thread has its own instance of the value.
+///
+/// # Example
+///
+/// ```
+/// use core::cell::Cell;
+///
+/// solana_allocator::global_slot!(static COUNTER: Cell<u64>);
+///
+/// fn next_id() -> u64 {
+///     let counter = COUNTER.get();
+///     counter.set(counter.get() + 1);
+///     counter.get()
+/// }
+///
+/// assert_eq!(1, next_id());
+/// assert_eq!(2, next_id());
+/// ```
+#[macro_export]
+macro_rules! global_slot {
+    ($(#[$meta:meta])* $visibility:vis static $name:ident: $T:ty $(;)?) => {
+        $(#[$meta])*
+        $visibility static $name: $crate::slot::GlobalSlot<$T> =
+            $crate::slot::GlobalSlot::new();
+    };
+}
+
+
+/// Declares mutable global objects with lazy initialisers.
+///
+/// ```ignore
+/// solana_static! {
+///     $visibility static $NAME: $Type = $init;
+///     ...
+/// }
+/// ```
+///
+/// The syntax is modelled on [`std::thread_local`].  Defines an ordinary
+/// `static $NAME: SolanaStatic<$Type>` whose [`get`](`SolanaStatic::get`)
+/// method returns a `&'static $Type` reference.  Unlike with
+/// [`global_slot`](`crate::global_slot`), `$Type` doesn’t need to be
+/// [`bytemuck::Zeroable`].  Instead, `$init` expression is evaluated on first
+/// access to initialise the value.  The value is never dropped.
+///
+/// Modifying the value requires interior mutability, e.g.
+/// [`Cell`](`core::cell::Cell`).  The same requirements as for
+/// [`global_slot`](`crate::global_slot`) apply.  In particular, when not
+/// building for Solana, each thread has its own instance of the value.
+///
+/// # Example
+///
+/// ```
+/// use core::cell::Cell;
+///
+/// solana_allocator::solana_static! {
+///     static COUNTER: Cell<u32> = Cell::new(5);
+/// }
+///
+/// assert_eq!(5, COUNTER.get().get());
+/// COUNTER.get().set(6);
+/// assert_eq!(6, COUNTER.with(Cell::get));
+/// ```
+#[macro_export]
+macro_rules! solana_static {
+    () => {};
+
+    ($(#[$meta:meta])* $visibility:vis static $name:ident: $T:ty = $init:expr
+     $(; $($rest:tt)*)?) => {
+        $(#[$meta])*
+        $visibility static $name: $crate::slot::SolanaStatic<$T> =
+            $crate::slot::SolanaStatic::new(|| $init);
+
+        $($crate::solana_static!($($rest)*);)?
+    };
+}
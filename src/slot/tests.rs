@@ -0,0 +1,152 @@
+use core::cell::Cell;
+
+use super::*;
+
+crate::global_slot!(static FIRST: Cell<u64>);
+crate::global_slot!(static SECOND: Cell<u64>);
+
+#[repr(align(64))]
+#[derive(bytemuck::Zeroable)]
+struct Aligned(Cell<[u8; 64]>);
+
+crate::global_slot!(static ALIGNED: Aligned);
+
+#[test]
+fn test_global_slot() {
+    assert_eq!(0, FIRST.get().get());
+    assert_eq!(0, SECOND.get().get());
+    FIRST.get().set(42);
+    assert_eq!(42, FIRST.get().get());
+    assert_eq!(0, SECOND.get().get());
+    assert!(core::ptr::eq(FIRST.get(), FIRST.get()));
+
+    let aligned = ALIGNED.get();
+    assert_eq!(0, core::ptr::from_ref(aligned) as usize % 64);
+    assert_eq!([0; 64], aligned.0.get());
+
+    // Each thread has its own instance.
+    std::thread::spawn(|| assert_eq!(0, FIRST.get().get())).join().unwrap();
+    assert_eq!(42, FIRST.get().get());
+}
+
+#[test]
+fn test_registry() {
+    let registry = Registry::new();
+    let layout = Layout::new::<u64>();
+    let alloc = |layout| unsafe { alloc::alloc::alloc_zeroed(layout) };
+    let keys = [0u8, 1u8, 2u8];
+    let first = registry.get_or_insert(&keys[0], layout, alloc);
+    let second = registry.get_or_insert(&keys[1], layout, alloc);
+    assert_ne!(first, second);
+    let head = registry.head();
+
+    // Existing slots don’t allocate.
+    let fail = |_| -> *mut u8 { panic!() };
+    assert_eq!(first, registry.get_or_insert(&keys[0], layout, fail));
+    assert_eq!(second, registry.get_or_insert(&keys[1], layout, fail));
+
+    // Slots registered after head are removed by truncate.  The registry
+    // never frees the memory so it’s leaked.
+    registry.get_or_insert(&keys[2], layout, alloc);
+    assert_eq!(1, unsafe { registry.truncate(head) });
+    assert_eq!(0, unsafe { registry.truncate(head) });
+    assert_eq!(first, registry.get_or_insert(&keys[0], layout, fail));
+}
+
+crate::solana_static! {
+    static COUNTER: Cell<u32> = Cell::new(5);
+    pub(crate) static NAME: std::string::String = "hello".into();
+}
+
+#[test]
+fn test_solana_static() {
+    assert_eq!(5, COUNTER.get().get());
+    COUNTER.get().set(6);
+    assert_eq!(6, COUNTER.with(Cell::get));
+    assert_eq!("hello", NAME.get());
+
+    // Each thread has its own instance.
+    std::thread::spawn(|| assert_eq!(5, COUNTER.get().get())).join().unwrap();
+    assert_eq!(6, COUNTER.get().get());
+}
+
+#[test]
+fn test_solana_static_lazy() {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static CALLS: AtomicU32 = AtomicU32::new(0);
+    crate::solana_static! {
+        static LAZY: u32 = CALLS.fetch_add(1, Ordering::SeqCst) + 10;
+    }
+
+    assert_eq!(0, CALLS.load(Ordering::SeqCst));
+    assert_eq!(10, *LAZY.get());
+    assert_eq!(10, *LAZY.get());
+    assert_eq!(1, CALLS.load(Ordering::SeqCst));
+}
+
+#[test]
+#[should_panic = "accessed during its initialisation"]
+fn test_solana_static_recursive() {
+    crate::solana_static! {
+        static RECURSIVE: u32 = *RECURSIVE.get() + 1;
+    }
+    RECURSIVE.get();
+}
+
+mod global {
+    use core::cell::Cell;
+
+    crate::custom_global!(
+        pub(super) struct Global {
+            pub(super) counter: Cell<u32>,
+        }
+    );
+}
+
+#[test]
+fn test_reset_globals() {
+    global::global().counter.set(42);
+    FIRST.get().set(42);
+    COUNTER.get().set(42);
+    assert_eq!(42, global::global().counter.get());
+
+    let old = global::global();
+    reset_globals();
+    assert_eq!(0, global::global().counter.get());
+    assert_eq!(0, FIRST.get().get());
+    assert_eq!(5, COUNTER.get().get());
+    // Old references remain valid.
+    assert_eq!(42, old.counter.get());
+}
+
+mod global_mut {
+    crate::custom_global!(
+        mut pub(super) struct Global {
+            pub(super) counter: u32,
+            pub(super) data: [u64; 4],
+        }
+    );
+}
+
+#[test]
+fn test_custom_global_mut() {
+    global_mut::with_global(|global| {
+        global.counter += 1;
+        global.data[1] = 42;
+    });
+    let counter = global_mut::with_global(|global| {
+        assert_eq!([0, 42, 0, 0], global.data);
+        global.counter
+    });
+    assert_eq!(1, counter);
+
+    reset_globals();
+    assert_eq!(0, global_mut::with_global(|global| global.counter));
+}
+
+#[test]
+#[should_panic = "global state already borrowed"]
+fn test_custom_global_mut_nested() {
+    global_mut::with_global(|_| global_mut::with_global(|_| ()));
+}
@@ -0,0 +1,87 @@
+//! Allocation statistics collected when `stats` feature is enabled.
+
+use core::cell::Cell;
+
+/// Allocation statistics of an allocator.
+///
+/// Returned by `stats` method of the allocators.  All counters are cumulative
+/// since the start of the program; they are not affected by
+/// [`BumpAllocator::rollback`](`crate::BumpAllocator::rollback`).  Objects
+/// which haven’t been freed before a rollback remain counted as live.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Number of bytes of the heap currently in use, i.e. distance between
+    /// the end of allocator’s header and the end of allocated memory.
+    ///
+    /// This includes memory leaked by the allocator, per-block metadata and
+    /// padding.
+    pub used: usize,
+    /// Largest value of [`Self::used`] observed so far.
+    pub peak: usize,
+    /// Number of bytes at the start of the heap reserved for allocator’s
+    /// internal data and the global state.
+    ///
+    /// This is not included in [`Self::used`] and [`Self::peak`].  Total heap
+    /// size needed by the program is `reserved + peak`.
+    pub reserved: usize,
+    /// Number of successful allocations.
+    pub allocs: usize,
+    /// Number of deallocations.
+    pub deallocs: usize,
+    /// Number of reallocations.
+    ///
+    /// Reallocations which need to move the data are additionally counted as
+    /// an allocation and a deallocation.
+    pub reallocs: usize,
+    /// Number of deallocations whose memory can be reused by subsequent
+    /// allocations.
+    pub reclaimed: usize,
+    /// Number of deallocations whose memory has been leaked.
+    ///
+    /// Bump allocator can reuse memory only if the most recently allocated
+    /// block is freed (unless `reclaim` or `size-classes` features are
+    /// enabled).  Other deallocations leak memory until the memory is freed by
+    /// a rollback.
+    pub leaked: usize,
+    /// Number of bytes lost to alignment padding.
+    pub padding: usize,
+    /// Number of bytes requested by allocations which haven’t been freed.
+    ///
+    /// Unlike [`Self::used`], this doesn’t include per-block metadata, padding
+    /// or memory which has been freed but not reclaimed.
+    pub live_bytes: usize,
+}
+
+// SAFETY: All fields are integers.
+unsafe impl bytemuck::Zeroable for Stats {}
+
+impl Stats {
+    /// Returns number of allocations which haven’t been freed.
+    pub fn live(&self) -> usize { self.allocs - self.deallocs }
+
+    /// Returns number of bytes of the heap in use which don’t hold live
+    /// allocations.
+    ///
+    /// This is mostly memory of objects which have been freed out of order.
+    /// It also includes per-block metadata and alignment padding.
+    pub fn unreclaimed(&self) -> usize {
+        self.used.saturating_sub(self.live_bytes)
+    }
+
+    /// Modifies statistics stored in a cell.
+    pub(crate) fn update(cell: &Cell<Self>, f: impl FnOnce(&mut Self)) {
+        let mut stats = cell.get();
+        f(&mut stats);
+        cell.set(stats);
+    }
+
+    /// Records that `used` bytes of the heap are in use and that `padding`
+    /// bytes have been lost to alignment.
+    pub(crate) fn record_use(cell: &Cell<Self>, used: usize, padding: usize) {
+        Self::update(cell, |stats| {
+            stats.peak = stats.peak.max(used);
+            stats.padding += padding;
+        })
+    }
+}
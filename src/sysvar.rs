@@ -0,0 +1,124 @@
+//! Discovery of the heap size from the Instructions sysvar.
+//!
+//! Heap size can be increased per-transaction with Compute Budget program’s
+//! `RequestHeapFrame` instruction.  Solana doesn’t tell the program how large
+//! its heap is but the program can find the instruction by looking at the
+//! Instructions sysvar account.  This module implements parsing of the sysvar
+//! without depending on `solana-program` crate.
+
+#[cfg(test)]
+mod tests;
+
+/// Address of the Instructions sysvar,
+/// `Sysvar1nstructions1111111111111111111111111`.
+pub const INSTRUCTIONS_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 24, 123, 209, 102, 53, 218, 212, 4, 85, 253, 194, 192,
+    193, 36, 198, 143, 33, 86, 117, 165, 219, 186, 203, 95, 8, 0, 0, 0,
+];
+
+/// Address of the Compute Budget program,
+/// `ComputeBudget111111111111111111111111111111`.
+pub const COMPUTE_BUDGET_PROGRAM_ID: [u8; 32] = [
+    3, 6, 70, 111, 229, 33, 23, 50, 255, 236, 173, 186, 114, 195, 155, 231,
+    188, 140, 229, 187, 197, 247, 18, 107, 44, 67, 155, 58, 64, 0, 0, 0,
+];
+
+/// Heap size used when transaction doesn’t request a heap frame.
+///
+/// This is the same as `solana_sdk::entrypoint::HEAP_LENGTH`.
+pub const DEFAULT_HEAP_FRAME: u32 = 32 * 1024;
+
+/// Largest heap frame which can be requested.
+///
+/// This is the same as `solana_compute_budget::MAX_HEAP_FRAME_BYTES`.
+pub const MAX_HEAP_FRAME: u32 = 256 * 1024;
+
+/// Granularity of the heap frame size.  Requested size must be a multiple of
+/// this.
+pub const HEAP_FRAME_GRANULARITY: u32 = 1024;
+
+/// Discriminant of `ComputeBudgetInstruction::RequestHeapFrame`.
+const REQUEST_HEAP_FRAME: u8 = 1;
+
+
+/// Returns heap frame size requested by the transaction.
+///
+/// `data` must be contents of the Instructions sysvar account.  Looks for
+/// Compute Budget program’s `RequestHeapFrame` instruction and returns the
+/// requested size.  If there’s no such instruction, returns
+/// [`DEFAULT_HEAP_FRAME`].  Returns `None` if the data is malformed.
+///
+/// Note that the function doesn’t verify that the data comes from the
+/// Instructions sysvar.  Caller is responsible for checking account’s address
+/// (e.g. by comparing it to [`INSTRUCTIONS_SYSVAR_ID`]).
+pub fn requested_heap_frame(data: &[u8]) -> Option<u32> {
+    let mut size = DEFAULT_HEAP_FRAME;
+    for instruction in Instructions::new(data)? {
+        let (program_id, data) = instruction?;
+        if program_id == &COMPUTE_BUDGET_PROGRAM_ID {
+            if let [REQUEST_HEAP_FRAME, a, b, c, d] = *data {
+                size = u32::from_le_bytes([a, b, c, d]);
+            }
+        }
+    }
+    Some(size)
+}
+
+
+/// Iterator over instructions serialised in the Instructions sysvar.
+///
+/// The sysvar data has the following format (all integers are little-endian):
+///
+/// ```text
+/// num_instructions: u16
+/// offsets:          [u16; num_instructions]
+/// instructions:     [Instruction; num_instructions]
+/// current_index:    u16
+///
+/// Instruction:
+///     num_accounts: u16
+///     accounts:     [(flags: u8, pubkey: [u8; 32]); num_accounts]
+///     program_id:   [u8; 32]
+///     data_len:     u16
+///     data:         [u8; data_len]
+/// ```
+///
+/// Yields `(program_id, data)` pairs for each instruction or `None` if
+/// instruction is malformed.
+struct Instructions<'a> {
+    data: &'a [u8],
+    offsets: &'a [u8],
+}
+
+impl<'a> Instructions<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let (count, rest) = read_u16(data)?;
+        let offsets = rest.get(..usize::from(count) * 2)?;
+        Some(Self { data, offsets })
+    }
+
+    /// Parses instruction at given offset.
+    fn parse(&self, offset: usize) -> Option<(&'a [u8; 32], &'a [u8])> {
+        let (num_accounts, rest) = read_u16(self.data.get(offset..)?)?;
+        let rest = rest.get(usize::from(num_accounts) * 33..)?;
+        let (program_id, rest) = rest.split_first_chunk::<32>()?;
+        let (len, rest) = read_u16(rest)?;
+        Some((program_id, rest.get(..usize::from(len))?))
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Option<(&'a [u8; 32], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (offset, rest) = read_u16(self.offsets)?;
+        self.offsets = rest;
+        Some(self.parse(usize::from(offset)))
+    }
+}
+
+/// Reads little-endian `u16` from the front of the slice.
+fn read_u16(data: &[u8]) -> Option<(u16, &[u8])> {
+    let (head, rest) = data.split_first_chunk::<2>()?;
+    Some((u16::from_le_bytes(*head), rest))
+}
@@ -0,0 +1,72 @@
+use super::*;
+
+/// Serialises instructions in the same format as the Instructions sysvar.
+///
+/// Each instruction is given as program id, number of accounts and data.
+fn serialise(instructions: &[([u8; 32], usize, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(instructions.len() as u16).to_le_bytes());
+    data.resize(2 + instructions.len() * 2, 0);
+    for (idx, (program_id, accounts, ix_data)) in
+        instructions.iter().enumerate()
+    {
+        let offset = (data.len() as u16).to_le_bytes();
+        data[2 + idx * 2..4 + idx * 2].copy_from_slice(&offset);
+        data.extend_from_slice(&(*accounts as u16).to_le_bytes());
+        for n in 0..*accounts {
+            data.push(1);
+            data.extend_from_slice(&[n as u8; 32]);
+        }
+        data.extend_from_slice(program_id);
+        data.extend_from_slice(&(ix_data.len() as u16).to_le_bytes());
+        data.extend_from_slice(ix_data);
+    }
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data
+}
+
+const OTHER_PROGRAM: [u8; 32] = [42; 32];
+
+#[test]
+fn test_requested_heap_frame() {
+    // No instructions.
+    let data = serialise(&[]);
+    assert_eq!(Some(DEFAULT_HEAP_FRAME), requested_heap_frame(&data));
+
+    // No RequestHeapFrame instruction.
+    let set_compute_unit_limit = [2, 0x40, 0x0d, 0x03, 0x00];
+    let data = serialise(&[
+        (COMPUTE_BUDGET_PROGRAM_ID, 0, &set_compute_unit_limit),
+        (OTHER_PROGRAM, 3, &[1, 0, 0, 4, 0]),
+    ]);
+    assert_eq!(Some(DEFAULT_HEAP_FRAME), requested_heap_frame(&data));
+
+    // RequestHeapFrame instruction present.
+    let request_heap_frame = [1, 0x00, 0x00, 0x04, 0x00];
+    let data = serialise(&[
+        (COMPUTE_BUDGET_PROGRAM_ID, 0, &set_compute_unit_limit),
+        (COMPUTE_BUDGET_PROGRAM_ID, 0, &request_heap_frame),
+        (OTHER_PROGRAM, 2, b"hello"),
+    ]);
+    assert_eq!(Some(256 * 1024), requested_heap_frame(&data));
+}
+
+#[test]
+fn test_requested_heap_frame_malformed() {
+    let request_heap_frame = [1, 0x00, 0x00, 0x01, 0x00];
+    let data = serialise(&[
+        (OTHER_PROGRAM, 1, &[]),
+        (COMPUTE_BUDGET_PROGRAM_ID, 0, &request_heap_frame),
+    ]);
+    assert_eq!(Some(64 * 1024), requested_heap_frame(&data));
+
+    // Truncated data.
+    for len in [0, 1, 3, 10, data.len() - 3] {
+        assert_eq!(None, requested_heap_frame(&data[..len]), "len: {len}");
+    }
+
+    // Offset pointing past the end of the data.
+    let mut bad = data.clone();
+    bad[4..6].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(None, requested_heap_frame(&bad));
+}
@@ -0,0 +1,644 @@
+//! Recording of allocator calls and their replay on the host.
+//!
+//! Heap usage of a transaction depends on the exact sequence of allocations
+//! and deallocations the program makes.  With `trace` Cargo feature,
+//! [`BumpAllocator::start_trace`](`crate::BumpAllocator::start_trace`) makes
+//! the allocator record every `alloc`, `dealloc` and `realloc` call it sees as
+//! a compact binary trace.  The trace is kept in a buffer reserved on the heap
+//! or, when the buffer fills up, flushed to program logs with `sol_log_data`.
+//!
+//! On the host, [`from_logs`] extracts the trace from transaction logs and
+//! [`replay`] feeds it into allocators provided by the crate.  This shows peak
+//! usage and fragmentation of a real transaction with different allocation
+//! strategies without redeploying the program.
+//!
+//! # Format
+//!
+//! The trace is a sequence of events (see [`Event`]).  Each event starts with
+//! a tag byte whose two lowest bits identify the kind of the event followed by
+//! its fields encoded as LEB128 variable-length integers.  For allocations,
+//! the remaining bits of the tag hold base two logarithm of the alignment.
+//! Addresses are offsets from the start of the heap with zero standing for
+//! a null pointer.  Zero-size allocations take no memory and aren’t recorded;
+//! reallocation of such a block is recorded as an allocation.
+//!
+//! When a trace buffer which isn’t flushed to logs fills up, recording stops
+//! and a single tag byte marking the trace as truncated is written.
+//!
+//! Chunks of the trace flushed to logs are emitted as `Program data: <magic>
+//! <chunk>` lines with both fields encoded in base64 and `<magic>` being
+//! [`MAGIC`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! solana_allocator::custom_heap!();
+//!
+//! fn process_instruction(
+//!     program_id: &Pubkey,
+//!     accounts: &[AccountInfo],
+//!     data: &[u8],
+//! ) -> ProgramResult {
+//!     allocator()
+//!         .start_trace(1024, solana_allocator::trace::Output::Log)
+//!         .unwrap();
+//!     let result = process(program_id, accounts, data);
+//!     allocator().stop_trace();
+//!     result
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+mod render;
+#[cfg(test)]
+mod tests;
+
+/// Value of the first field of `Program data:` log lines holding chunks of
+/// a trace.
+pub const MAGIC: &[u8] = b"solana-allocator: trace";
+
+/// Tag of [`Event::Alloc`].
+const ALLOC: u8 = 0;
+/// Tag of [`Event::Dealloc`].
+const DEALLOC: u8 = 1;
+/// Tag of [`Event::Realloc`].
+const REALLOC: u8 = 2;
+/// Tag marking the trace as truncated.
+pub(crate) const TRUNCATED: u8 = 3;
+
+/// Maximum length of an encoded `usize`.
+const VARINT_MAX: usize = (usize::BITS as usize).div_ceil(7);
+
+
+/// Where the allocator writes the trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// Keep the trace in the buffer reserved on the heap.  Once the buffer
+    /// fills up, recording stops and the trace is marked as truncated.
+    Buffer,
+    /// Flush the buffer to program logs with `sol_log_data` whenever it fills
+    /// up and when recording stops.  When not building for Solana, the data is
+    /// printed to standard error in the same format.
+    Log,
+}
+
+
+/// A single allocator call.
+///
+/// Addresses are offsets from the start of the heap.  Zero address means
+/// a null pointer, i.e. that the call failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Allocation of a block of given size and alignment at `addr`.
+    Alloc { addr: usize, size: usize, align: usize },
+    /// Deallocation of the block at `addr`.
+    Dealloc { addr: usize },
+    /// Reallocation of the block at `addr` to `new_size` bytes which moved it
+    /// to `new_addr`.
+    Realloc { addr: usize, new_size: usize, new_addr: usize },
+}
+
+impl Event {
+    /// Maximum length of an encoded event.
+    pub const MAX_LEN: usize = 1 + 3 * VARINT_MAX;
+
+    /// Encodes the event into `buf` and returns length of the encoding.
+    pub fn encode(&self, buf: &mut [u8; Self::MAX_LEN]) -> usize {
+        let (tag, fields) = match *self {
+            Self::Alloc { addr, size, align } => {
+                let tag = ALLOC | (align.trailing_zeros() as u8) << 2;
+                (tag, [addr, size, 0])
+            }
+            Self::Dealloc { addr } => (DEALLOC, [addr, 0, 0]),
+            Self::Realloc { addr, new_size, new_addr } => {
+                (REALLOC, [addr, new_size, new_addr])
+            }
+        };
+        buf[0] = tag;
+        let mut len = 1;
+        for value in &fields[..self.field_count()] {
+            len += encode_varint(*value, &mut buf[len..]);
+        }
+        len
+    }
+
+    /// Decodes an event from the front of `bytes`.  Returns the event and
+    /// length of its encoding.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let (&tag, mut rest) = bytes.split_first().ok_or(Error::Malformed)?;
+        let mut next = || {
+            let (value, len) = decode_varint(rest).ok_or(Error::Malformed)?;
+            rest = &rest[len..];
+            Ok(value)
+        };
+        let event = match tag & 3 {
+            ALLOC => {
+                let align = 1usize
+                    .checked_shl(u32::from(tag >> 2))
+                    .ok_or(Error::Malformed)?;
+                Self::Alloc { addr: next()?, size: next()?, align }
+            }
+            DEALLOC if tag == DEALLOC => Self::Dealloc { addr: next()? },
+            REALLOC if tag == REALLOC => Self::Realloc {
+                addr: next()?,
+                new_size: next()?,
+                new_addr: next()?,
+            },
+            TRUNCATED if tag == TRUNCATED => return Err(Error::Truncated),
+            _ => return Err(Error::Malformed),
+        };
+        Ok((event, bytes.len() - rest.len()))
+    }
+
+    /// Returns number of variable-length fields of the event.
+    fn field_count(&self) -> usize {
+        match self {
+            Self::Alloc { .. } => 2,
+            Self::Dealloc { .. } => 1,
+            Self::Realloc { .. } => 3,
+        }
+    }
+}
+
+
+/// Error decoding a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The trace buffer filled up and the rest of the trace wasn’t recorded.
+    Truncated,
+    /// The trace is corrupted.
+    Malformed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str(match self {
+            Self::Truncated => "trace truncated",
+            Self::Malformed => "malformed trace",
+        })
+    }
+}
+
+impl core::error::Error for Error {}
+
+
+/// Returns iterator over events of a trace.
+///
+/// The iterator stops after the first error.
+pub fn events(trace: &[u8]) -> Events<'_> { Events(trace) }
+
+/// Iterator over events of a trace returned by [`events`].
+#[derive(Clone, Debug)]
+pub struct Events<'a>(&'a [u8]);
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(match Event::decode(self.0) {
+            Ok((event, len)) => {
+                self.0 = &self.0[len..];
+                Ok(event)
+            }
+            Err(err) => {
+                self.0 = &[];
+                Err(err)
+            }
+        })
+    }
+}
+
+
+/// Extracts trace flushed to logs (see [`Output::Log`]) from transaction logs.
+///
+/// Chunks of the trace are concatenated in order they appear.  Lines which
+/// don’t hold chunks of a trace are ignored.
+pub fn from_logs<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<u8> {
+    let mut trace = Vec::new();
+    for line in lines {
+        let Some((_, data)) = line.split_once("Program data: ") else {
+            continue;
+        };
+        let mut fields = data.split_whitespace();
+        let magic = fields.next().and_then(crate::base64::decode);
+        if magic.as_deref() != Some(MAGIC) {
+            continue;
+        }
+        if let Some(chunk) = fields.next().and_then(crate::base64::decode) {
+            trace.extend_from_slice(&chunk);
+        }
+    }
+    trace
+}
+
+
+/// Returns trace held in `data` which is either transaction logs with the
+/// trace flushed to them (see [`from_logs`]) or a raw trace.
+pub fn load(data: Vec<u8>) -> Vec<u8> {
+    if let Ok(text) = core::str::from_utf8(&data) {
+        let trace = from_logs(text.lines());
+        if !trace.is_empty() {
+            return trace;
+        }
+    }
+    data
+}
+
+/// Reads trace from file at given path or, if `None`, from standard input.
+///
+/// The input is interpreted as with [`load`].
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+pub fn read(path: Option<&str>) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    match path {
+        Some(path) => std::fs::File::open(path)?.read_to_end(&mut data)?,
+        None => std::io::stdin().lock().read_to_end(&mut data)?,
+    };
+    Ok(load(data))
+}
+
+
+/// Allocation strategy a trace can be replayed with.
+///
+/// Behaviour of each allocator depends on enabled Cargo features (such as
+/// `reclaim` or `size-classes`) so to try variants of the bump allocator, the
+/// replayer must be built with different features.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// [`BumpAllocator`](`crate::BumpAllocator`).
+    Bump,
+    /// [`FreeListAllocator`](`crate::FreeListAllocator`).
+    FreeList,
+}
+
+/// Results of replaying a trace.
+///
+/// Usage values are offsets from the start of the heap, i.e. they include
+/// space reserved for allocator’s internal data, and are directly comparable
+/// with heap frame size.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of events in the trace.
+    pub events: usize,
+    /// Number of allocations and reallocations which succeeded when the
+    /// trace was recorded but failed during replay.
+    pub failed: usize,
+    /// Number of events which couldn’t be replayed, i.e. calls which failed
+    /// when the trace was recorded and calls referring to blocks allocated
+    /// before recording started.
+    pub skipped: usize,
+    /// Whether the trace was truncated.
+    pub truncated: bool,
+    /// Peak heap usage.
+    pub peak: usize,
+    /// Heap usage at the end of the trace.
+    pub end: usize,
+    /// Largest number of bytes of the heap in use which didn’t hold live
+    /// allocations.  See [`Stats::unreclaimed`](`crate::Stats::unreclaimed`).
+    pub fragmentation: usize,
+}
+
+/// Replays a trace with allocator of given strategy on a heap of given size.
+///
+/// Returns an error if the trace is malformed.  A truncated trace is replayed
+/// up to the point it was truncated.  Panics if the heap is too small to hold
+/// allocator’s header.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+pub fn replay(
+    trace: &[u8],
+    strategy: Strategy,
+    heap_size: usize,
+) -> Result<Summary, Error> {
+    with_allocator(strategy, heap_size, |allocator, _, stats| {
+        replay_with(allocator, stats, trace, |_, _| ())
+    })
+}
+
+/// Blocks allocated during replay keyed by their addresses in the trace.
+/// Null pointer stands for a block whose allocation failed during replay.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+type Blocks =
+    alloc::collections::BTreeMap<usize, (*mut u8, alloc::alloc::Layout)>;
+
+/// Creates allocator of given strategy on a heap of given size and calls `f`
+/// with it, start of its heap and function returning its statistics.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+fn with_allocator<R>(
+    strategy: Strategy,
+    heap_size: usize,
+    f: impl FnOnce(
+        &dyn alloc::alloc::GlobalAlloc,
+        usize,
+        &dyn Fn() -> crate::Stats,
+    ) -> R,
+) -> R {
+    match strategy {
+        Strategy::Bump => {
+            let allocator =
+                crate::BumpAllocator::<()>::with_capacity(heap_size);
+            let start = allocator.heap_start() as usize;
+            f(&allocator, start, &|| allocator.stats())
+        }
+        Strategy::FreeList => {
+            let allocator =
+                crate::FreeListAllocator::<()>::with_capacity(heap_size);
+            let start = allocator.heap_start() as usize;
+            f(&allocator, start, &|| allocator.stats())
+        }
+    }
+}
+
+/// Replays a trace with given allocator.  `stats` returns allocator’s
+/// statistics.  `observe` is called after each event with blocks which are
+/// live at that point.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+fn replay_with(
+    allocator: &dyn alloc::alloc::GlobalAlloc,
+    stats: &dyn Fn() -> crate::Stats,
+    trace: &[u8],
+    mut observe: impl FnMut(Event, &Blocks),
+) -> Result<Summary, Error> {
+    use alloc::alloc::Layout;
+
+    let mut blocks = Blocks::new();
+    let mut summary = Summary::default();
+    for event in events(trace) {
+        let event = match event {
+            Ok(event) => event,
+            Err(Error::Truncated) => {
+                summary.truncated = true;
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        summary.events += 1;
+        match event {
+            Event::Alloc { addr: 0, .. } => summary.skipped += 1,
+            // Zero-size allocations take no memory so there’s nothing to
+            // replay.
+            Event::Alloc { size: 0, .. } => (),
+            Event::Alloc { addr, size, align } => {
+                let layout = Layout::from_size_align(size, align)
+                    .map_err(|_| Error::Malformed)?;
+                // SAFETY: Layout has non-zero size.
+                let ptr = unsafe { allocator.alloc(layout) };
+                summary.failed += usize::from(ptr.is_null());
+                blocks.insert(addr, (ptr, layout));
+            }
+            Event::Dealloc { addr } => match blocks.remove(&addr) {
+                Some((ptr, _)) if ptr.is_null() => (),
+                // SAFETY: ptr is a live allocation made with layout.
+                Some((ptr, layout)) => unsafe {
+                    allocator.dealloc(ptr, layout)
+                },
+                None => summary.skipped += 1,
+            },
+            Event::Realloc { new_addr: 0, .. } => summary.skipped += 1,
+            Event::Realloc { addr, new_size, new_addr } => 'realloc: {
+                let Some((ptr, layout)) = blocks.remove(&addr) else {
+                    summary.skipped += 1;
+                    break 'realloc;
+                };
+                let new_layout =
+                    Layout::from_size_align(new_size, layout.align())
+                        .ok()
+                        .filter(|layout| layout.size() != 0)
+                        .ok_or(Error::Malformed)?;
+                let mut block = (ptr, layout);
+                if !ptr.is_null() {
+                    // SAFETY: ptr is a live allocation made with layout and
+                    // we’ve checked that the new layout is valid.
+                    let new_ptr =
+                        unsafe { allocator.realloc(ptr, layout, new_size) };
+                    if new_ptr.is_null() {
+                        summary.failed += 1;
+                    } else {
+                        block = (new_ptr, new_layout);
+                    }
+                }
+                blocks.insert(new_addr, block);
+            }
+        }
+        summary.fragmentation =
+            summary.fragmentation.max(stats().unreclaimed());
+        observe(event, &blocks);
+    }
+    let stats = stats();
+    summary.peak = stats.reserved + stats.peak;
+    summary.end = stats.reserved + stats.used;
+    Ok(summary)
+}
+
+
+/// Kind of a region of the heap in a [`Frame`].
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Allocator’s internal data and the global state at the start of the
+    /// heap.
+    Header,
+    /// Live allocation.
+    Live,
+    /// Memory of a freed allocation which hasn’t been reclaimed.
+    Freed,
+    /// Memory in use which isn’t part of any allocation, i.e. alignment
+    /// padding and per-block metadata.
+    Padding,
+}
+
+/// Contiguous region of the heap of a single [`Kind`].
+///
+/// Offsets are relative to the start of the heap.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub kind: Kind,
+}
+
+/// Layout of the heap after an event.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The event as recorded in the trace.
+    pub event: Event,
+    /// End of memory in use.
+    pub end: usize,
+    /// Regions of the heap in order of their addresses.  They cover the heap
+    /// from its start up to [`Self::end`] without gaps.
+    pub regions: Vec<Region>,
+}
+
+/// Layout of the heap over the course of a trace.  Returned by [`timeline`].
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timeline {
+    /// Layout of the heap after each event.
+    pub frames: Vec<Frame>,
+    /// Results of the replay.
+    pub summary: Summary,
+}
+
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+impl Timeline {
+    /// Returns index of the first frame with the largest end of memory in
+    /// use or `None` if there are no frames.
+    ///
+    /// Note that [`Summary::peak`] may be larger since it includes transient
+    /// usage within a reallocation which moves the block.
+    pub fn peak_frame(&self) -> Option<usize> {
+        let peak = self.frames.iter().map(|frame| frame.end).max()?;
+        self.frames.iter().position(|frame| frame.end == peak)
+    }
+}
+
+/// Replays a trace like [`replay`] and returns layout of the heap after each
+/// event.
+///
+/// Memory of freed blocks which is still in use is reported as
+/// [`Kind::Freed`] until it’s reused or reclaimed.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+pub fn timeline(
+    trace: &[u8],
+    strategy: Strategy,
+    heap_size: usize,
+) -> Result<Timeline, Error> {
+    with_allocator(strategy, heap_size, |allocator, start, stats| {
+        let reserved = stats().reserved;
+        let mut frames = Vec::new();
+        let mut live = Vec::new();
+        let mut freed = Vec::new();
+        let summary = replay_with(allocator, stats, trace, |event, blocks| {
+            let end = reserved + stats().used;
+            let prev = core::mem::take(&mut live);
+            live.extend(blocks.values().filter(|(ptr, _)| !ptr.is_null()).map(
+                |(ptr, layout)| {
+                    let offset = *ptr as usize - start;
+                    (offset, offset + layout.size())
+                },
+            ));
+            live.sort_unstable();
+            freed
+                .extend(prev.into_iter().filter(|range| !live.contains(range)));
+            freed = subtract(&freed, &live, end);
+            frames.push(Frame {
+                event,
+                end,
+                regions: regions(reserved, end, &live, &freed),
+            });
+        })?;
+        Ok(Timeline { frames, summary })
+    })
+}
+
+/// Returns `ranges` with parts overlapping `live` ranges or lying at or above
+/// `end` removed.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+fn subtract(
+    ranges: &[(usize, usize)],
+    live: &[(usize, usize)],
+    end: usize,
+) -> Vec<(usize, usize)> {
+    let mut result = Vec::new();
+    for &(mut start, range_end) in ranges {
+        let range_end = range_end.min(end);
+        for &(live_start, live_end) in live {
+            if live_end <= start || range_end <= live_start {
+                continue;
+            }
+            if start < live_start {
+                result.push((start, live_start));
+            }
+            start = start.max(live_end);
+        }
+        if start < range_end {
+            result.push((start, range_end));
+        }
+    }
+    result.sort_unstable();
+    result
+}
+
+/// Returns regions of the heap with given live and freed ranges.  Parts of
+/// `[reserved, end)` not covered by either are padding.
+#[cfg(all(any(test, feature = "testing"), not(target_os = "solana")))]
+fn regions(
+    reserved: usize,
+    end: usize,
+    live: &[(usize, usize)],
+    freed: &[(usize, usize)],
+) -> Vec<Region> {
+    let mut ranges = live
+        .iter()
+        .map(|&range| (range, Kind::Live))
+        .chain(freed.iter().map(|&range| (range, Kind::Freed)))
+        .collect::<Vec<_>>();
+    ranges.sort_unstable_by_key(|&(range, _)| range);
+
+    let mut regions = Vec::<Region>::new();
+    let mut push = |start: usize, end: usize, kind: Kind| {
+        if start >= end {
+            return;
+        }
+        match regions.last_mut() {
+            Some(last) if last.kind == kind && last.end == start => {
+                last.end = end
+            }
+            _ => regions.push(Region { start, end, kind }),
+        }
+    };
+    push(0, reserved, Kind::Header);
+    let mut pos = reserved;
+    for ((start, range_end), kind) in ranges {
+        push(pos, start, Kind::Padding);
+        push(start.max(pos), range_end, kind);
+        pos = pos.max(range_end);
+    }
+    push(pos, end, Kind::Padding);
+    regions
+}
+
+
+/// Encodes `value` as LEB128 into `buf` and returns length of the encoding.
+fn encode_varint(mut value: usize, buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+/// Decodes LEB128 value from the front of `bytes`.  Returns the value and
+/// length of its encoding or `None` if the encoding is invalid.
+fn decode_varint(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (idx, &byte) in bytes.iter().enumerate().take(VARINT_MAX) {
+        let bits = usize::from(byte & 0x7F);
+        let shift = 7 * idx as u32;
+        if shift > 0 && bits >> (usize::BITS - shift) != 0 {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
@@ -0,0 +1,176 @@
+//! Rendering of [`Timeline`] as text or SVG image.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::{Event, Kind, Timeline};
+use crate::sysvar::DEFAULT_HEAP_FRAME;
+
+/// Width of the heap in SVG output in pixels.
+const SVG_WIDTH: usize = 800;
+/// Height of a single frame in SVG output in pixels.
+const SVG_ROW: usize = 4;
+/// Space for labels left of and above the heap in SVG output in pixels.
+const SVG_MARGIN: usize = 40;
+
+/// Returns short description of an event.
+fn describe(event: &Event) -> String {
+    match *event {
+        Event::Alloc { addr, size, .. } => format!("alloc {size} @{addr}"),
+        Event::Dealloc { addr } => format!("dealloc @{addr}"),
+        Event::Realloc { addr, new_size, new_addr } => {
+            format!("realloc @{addr} {new_size} @{new_addr}")
+        }
+    }
+}
+
+/// Returns character representing given kind of region in text output.
+fn symbol(kind: Option<Kind>) -> char {
+    match kind {
+        Some(Kind::Header) => 'H',
+        Some(Kind::Live) => '#',
+        Some(Kind::Freed) => '.',
+        Some(Kind::Padding) => ':',
+        None => ' ',
+    }
+}
+
+impl Timeline {
+    /// Renders the timeline as text with `width` columns per frame covering
+    /// `scale` bytes of the heap.
+    ///
+    /// Each column shows the kind of region which takes most of its bytes or
+    /// is blank if none of its bytes are in use.  The 32 KiB boundary of the
+    /// default heap frame is marked with `|` and the frame at which heap
+    /// usage peaked with `<- peak`.
+    pub fn render_text(&self, scale: usize, width: usize) -> String {
+        let per_column = scale.div_ceil(width);
+        let boundary = DEFAULT_HEAP_FRAME as usize / per_column;
+        let peak = self.peak_frame();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# {per_column} bytes per column; H header, # live, . freed, : \
+             padding, | 32 KiB boundary",
+        );
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let mut row = String::with_capacity(width + 1);
+            for column in 0..width {
+                if column == boundary {
+                    row.push('|');
+                }
+                let (start, end) =
+                    (column * per_column, (column + 1) * per_column);
+                let mut bytes = [0; 4];
+                for region in &frame.regions {
+                    let overlap = region
+                        .end
+                        .min(end)
+                        .saturating_sub(region.start.max(start));
+                    bytes[region.kind as usize] += overlap;
+                }
+                let kind =
+                    [Kind::Header, Kind::Live, Kind::Freed, Kind::Padding]
+                        .into_iter()
+                        .filter(|&kind| bytes[kind as usize] > 0)
+                        .max_by_key(|&kind| bytes[kind as usize]);
+                row.push(symbol(kind));
+            }
+            if boundary >= width {
+                row.push('|');
+            }
+            let mark = if Some(idx) == peak { " <- peak" } else { "" };
+            let _ = writeln!(
+                out,
+                "{:>5} {:<32} {:>7} {row}{mark}",
+                idx + 1,
+                describe(&frame.event),
+                frame.end,
+            );
+        }
+        out
+    }
+
+    /// Renders the timeline as an SVG image covering `scale` bytes of the
+    /// heap.
+    pub fn render_svg(&self, scale: usize) -> String {
+        let x = |offset: usize| {
+            SVG_MARGIN as f64 + offset as f64 * SVG_WIDTH as f64 / scale as f64
+        };
+        let height = self.frames.len() * SVG_ROW;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+             height=\"{}\" font-family=\"monospace\" font-size=\"10\">",
+            SVG_WIDTH + 2 * SVG_MARGIN,
+            height + 2 * SVG_MARGIN,
+        );
+        out.push_str("<style>.h{fill:#999}.l{fill:#4a7ebb}");
+        out.push_str(".f{fill:#d9534f}.p{fill:#f0ad4e}</style>\n");
+        for (idx, (class, label)) in
+            [("h", "header"), ("l", "live"), ("f", "freed"), ("p", "padding")]
+                .into_iter()
+                .enumerate()
+        {
+            let left = SVG_MARGIN + idx * 80;
+            let _ = writeln!(
+                out,
+                "<rect class=\"{class}\" x=\"{left}\" y=\"8\" width=\"10\" \
+                 height=\"10\"/><text x=\"{}\" y=\"17\">{label}</text>",
+                left + 14,
+            );
+        }
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let y = SVG_MARGIN + idx * SVG_ROW;
+            let _ = writeln!(
+                out,
+                "<g><title>{}: {}</title>",
+                idx + 1,
+                describe(&frame.event)
+            );
+            for region in &frame.regions {
+                let class = match region.kind {
+                    Kind::Header => "h",
+                    Kind::Live => "l",
+                    Kind::Freed => "f",
+                    Kind::Padding => "p",
+                };
+                let _ = writeln!(
+                    out,
+                    "<rect class=\"{class}\" x=\"{:.2}\" y=\"{y}\" \
+                     width=\"{:.2}\" height=\"{SVG_ROW}\"/>",
+                    x(region.start),
+                    x(region.end) - x(region.start),
+                );
+            }
+            out.push_str("</g>\n");
+        }
+        let boundary = x(DEFAULT_HEAP_FRAME as usize);
+        let _ = writeln!(
+            out,
+            "<line x1=\"{boundary:.2}\" y1=\"{}\" x2=\"{boundary:.2}\" \
+             y2=\"{}\" stroke=\"black\" stroke-dasharray=\"4 2\"/><text \
+             x=\"{boundary:.2}\" y=\"{}\" text-anchor=\"middle\">32 KiB</text>",
+            SVG_MARGIN - 4,
+            SVG_MARGIN + height,
+            SVG_MARGIN - 6,
+        );
+        if let Some(peak) = self.peak_frame() {
+            let y = SVG_MARGIN + peak * SVG_ROW + SVG_ROW / 2;
+            let end = self.frames[peak].end;
+            let _ = writeln!(
+                out,
+                "<line x1=\"{SVG_MARGIN}\" y1=\"{y}\" x2=\"{:.2}\" y2=\"{y}\" \
+                 stroke=\"black\"/><text x=\"{:.2}\" y=\"{}\">peak \
+                 {end}</text>",
+                x(end),
+                x(end) + 4.0,
+                y + 3,
+            );
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
@@ -0,0 +1,320 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::string::ToString;
+
+use super::*;
+use crate::base64::Base64;
+
+/// Decodes all events of a trace.
+fn decode_all(trace: &[u8]) -> Vec<Result<Event, Error>> {
+    events(trace).collect()
+}
+
+/// Encodes events into a trace.
+fn encode_all(events: &[Event]) -> Vec<u8> {
+    let mut trace = Vec::new();
+    for event in events {
+        let mut buf = [0; Event::MAX_LEN];
+        let len = event.encode(&mut buf);
+        trace.extend_from_slice(&buf[..len]);
+    }
+    trace
+}
+
+#[test]
+fn test_encode_decode() {
+    let events = [
+        Event::Alloc { addr: 64, size: 100, align: 8 },
+        Event::Alloc { addr: 0, size: usize::MAX, align: 1 << 20 },
+        Event::Dealloc { addr: 64 },
+        Event::Realloc { addr: 200, new_size: 1 << 40, new_addr: 0 },
+        Event::Realloc {
+            addr: usize::MAX,
+            new_size: usize::MAX,
+            new_addr: usize::MAX,
+        },
+    ];
+    let trace = encode_all(&events);
+    let want = events.iter().copied().map(Ok).collect::<Vec<_>>();
+    assert_eq!(want, decode_all(&trace));
+
+    // Small events are compact.
+    assert_eq!(3, encode_all(&events[..1]).len());
+    assert_eq!(2, encode_all(&events[2..3]).len());
+    assert_eq!(Event::MAX_LEN, encode_all(&events[4..]).len());
+
+    // Decoding stops at truncation marker or malformed event.
+    let mut trace = encode_all(&events[..1]);
+    trace.extend_from_slice(&[TRUNCATED, DEALLOC, 0]);
+    assert_eq!(vec![Ok(events[0]), Err(Error::Truncated)], decode_all(&trace));
+    for bad in [&[0x05, 0][..], &[DEALLOC], &[DEALLOC, 0x80], &[0xFF, 1, 1]] {
+        assert_eq!(vec![Err(Error::Malformed)], decode_all(bad), "{bad:?}");
+    }
+    let mut overflow = vec![DEALLOC];
+    overflow.extend_from_slice(&[0xFF; VARINT_MAX - 1]);
+    overflow.push(0x7F);
+    assert_eq!(vec![Err(Error::Malformed)], decode_all(&overflow));
+}
+
+#[test]
+fn test_from_logs() {
+    let first = encode_all(&[Event::Alloc { addr: 64, size: 8, align: 8 }]);
+    let second = encode_all(&[Event::Dealloc { addr: 64 }]);
+    let magic = Base64(MAGIC);
+    let logs = [
+        "Program 11111111111111111111111111111111 invoke [1]".to_string(),
+        std::format!("Program data: {magic} {}", Base64(&first)),
+        "Program log: hello".to_string(),
+        // Data logged by the program is ignored.
+        std::format!("Program data: {} {}", Base64(b"event"), Base64(&first)),
+        std::format!("Program data: {magic} {}", Base64(&second)),
+    ];
+    let trace = from_logs(logs.iter().map(String::as_str));
+    assert_eq!([first, second].concat(), trace);
+}
+
+/// Records a trace of a few allocations made with a fresh allocator.
+/// Returns the trace and pointers returned by the allocator in order.
+fn record() -> (Vec<u8>, [*mut u8; 4]) {
+    let allocator = crate::BumpAllocator::<()>::new(4096);
+    allocator.start_trace(256, Output::Buffer).unwrap();
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let large = Layout::from_size_align(1000, 16).unwrap();
+    unsafe {
+        let first = allocator.alloc(large);
+        let second = allocator.alloc(small);
+        allocator.dealloc(first, large);
+        let third = allocator.alloc(large);
+        // Reallocation which moves the block is recorded as a single event.
+        let fourth = allocator.realloc(second, small, 200);
+        allocator.dealloc(third, large);
+        let trace = allocator.stop_trace().to_vec();
+        (trace, [first, second, third, fourth])
+    }
+}
+
+#[test]
+fn test_record() {
+    let (trace, ptrs) = record();
+    let events = decode_all(&trace);
+    let events = events.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+    // Addresses are offsets from the start of the heap.
+    let Some(&Event::Alloc { addr, .. }) = events.first() else {
+        panic!("unexpected events: {events:?}");
+    };
+    let base = ptrs[0] as usize - addr;
+    let [first, second, third, fourth] = ptrs.map(|ptr| ptr as usize - base);
+    let want = [
+        Event::Alloc { addr: first, size: 1000, align: 16 },
+        Event::Alloc { addr: second, size: 100, align: 8 },
+        Event::Dealloc { addr: first },
+        Event::Alloc { addr: third, size: 1000, align: 16 },
+        Event::Realloc { addr: second, new_size: 200, new_addr: fourth },
+        Event::Dealloc { addr: third },
+    ];
+    assert_eq!(&want[..], &events[..]);
+}
+
+#[test]
+fn test_truncated() {
+    let allocator = crate::BumpAllocator::<()>::new(4096);
+    allocator.start_trace(0, Output::Buffer).unwrap();
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    for _ in 0..Event::MAX_LEN {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    let trace = allocator.stop_trace();
+    let events = decode_all(trace);
+    assert_eq!(Some(&Err(Error::Truncated)), events.last());
+    assert!(events[..events.len() - 1].iter().all(Result::is_ok));
+
+    let summary = replay(trace, Strategy::Bump, 4096).unwrap();
+    assert!(summary.truncated);
+    assert_eq!(events.len() - 1, summary.events);
+}
+
+#[test]
+fn test_log() {
+    let allocator = crate::BumpAllocator::<()>::new(4096);
+    allocator.start_trace(0, Output::Log).unwrap();
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    for _ in 0..Event::MAX_LEN {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // Trace has been flushed to logs.
+    assert_eq!(&[] as &[u8], allocator.stop_trace());
+}
+
+#[test]
+fn test_replay() {
+    let (trace, _) = record();
+    let bump = replay(&trace, Strategy::Bump, 4096).unwrap();
+    let free_list = replay(&trace, Strategy::FreeList, 4096).unwrap();
+    for summary in [bump, free_list] {
+        assert_eq!(
+            (6, 0, 0),
+            (summary.events, summary.failed, summary.skipped)
+        );
+        assert!(!summary.truncated);
+    }
+    // Bump allocator cannot reuse memory freed out of order.
+    assert!(bump.peak > free_list.peak + 900, "{bump:?} {free_list:?}");
+    assert!(bump.fragmentation >= 1000);
+
+    // On a smaller heap, the bump allocator runs out of memory.
+    let summary = replay(&trace, Strategy::Bump, free_list.peak).unwrap();
+    assert_ne!(0, summary.failed);
+    let summary = replay(&trace, Strategy::FreeList, free_list.peak).unwrap();
+    assert_eq!(0, summary.failed);
+}
+
+#[test]
+fn test_replay_skipped() {
+    let trace = encode_all(&[
+        // Allocation which failed when recorded.
+        Event::Alloc { addr: 0, size: 8, align: 8 },
+        Event::Alloc { addr: 64, size: 8, align: 8 },
+        // Block allocated before recording started.
+        Event::Dealloc { addr: 128 },
+        Event::Realloc { addr: 128, new_size: 16, new_addr: 256 },
+        Event::Dealloc { addr: 64 },
+    ]);
+    let summary = replay(&trace, Strategy::FreeList, 1024).unwrap();
+    assert_eq!((5, 0, 3), (summary.events, summary.failed, summary.skipped));
+
+    // Zero-size allocation takes no memory.
+    let trace = encode_all(&[Event::Alloc { addr: 64, size: 0, align: 8 }]);
+    let summary = replay(&trace, Strategy::Bump, 1024).unwrap();
+    assert_eq!((1, 0, 0), (summary.events, summary.failed, summary.skipped));
+
+    let trace =
+        encode_all(&[Event::Alloc { addr: 64, size: usize::MAX, align: 8 }]);
+    assert_eq!(Err(Error::Malformed), replay(&trace, Strategy::Bump, 1024));
+}
+
+#[test]
+#[cfg(feature = "allocator-api2")]
+fn test_replay_zero_size() {
+    use allocator_api2::alloc::Allocator;
+
+    let allocator = crate::BumpAllocator::<()>::new(4096);
+    allocator.start_trace(256, Output::Buffer).unwrap();
+    let empty = Layout::from_size_align(0, 8).unwrap();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    // Growing a zero-size block is recorded as an allocation.
+    let ptr = unsafe { allocator.alloc(empty) };
+    let block = unsafe { allocator.realloc(ptr, empty, layout.size()) };
+    // Zero-size block at the end shares its address with whatever is
+    // allocated next so it’s left out of the trace.
+    let ptr = unsafe { allocator.alloc(empty) };
+    unsafe { allocator.dealloc(ptr, empty) };
+    // `Allocator` doesn’t allocate zero-size blocks at all.
+    let ptr = (&allocator).allocate(empty).unwrap();
+    unsafe { (&allocator).deallocate(ptr.cast(), empty) };
+    unsafe { allocator.dealloc(block, layout) };
+    let trace = allocator.stop_trace().to_vec();
+    let events = decode_all(&trace);
+    let Some(&Ok(Event::Alloc { addr, .. })) = events.first() else {
+        panic!("unexpected events: {events:?}");
+    };
+    let want = [
+        Ok(Event::Alloc { addr, size: 100, align: 8 }),
+        Ok(Event::Dealloc { addr }),
+    ];
+    assert_eq!(&want[..], &events[..]);
+
+    for strategy in [Strategy::Bump, Strategy::FreeList] {
+        let summary = replay(&trace, strategy, 4096).unwrap();
+        assert_eq!(
+            (2, 0, 0),
+            (summary.events, summary.failed, summary.skipped)
+        );
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let (trace, _) = record();
+    let bump = timeline(&trace, Strategy::Bump, 4096).unwrap();
+    assert_eq!(replay(&trace, Strategy::Bump, 4096).unwrap(), bump.summary);
+    assert_eq!(6, bump.frames.len());
+    for frame in &bump.frames {
+        // Regions cover the heap without gaps starting with the header.
+        assert_eq!(Kind::Header, frame.regions[0].kind, "{frame:?}");
+        assert_eq!(0, frame.regions[0].start);
+        assert_eq!(frame.end, frame.regions.last().unwrap().end);
+        for pair in frame.regions.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start, "{frame:?}");
+        }
+    }
+
+    let freed = |frame: &Frame| {
+        frame
+            .regions
+            .iter()
+            .filter(|region| region.kind == Kind::Freed)
+            .map(|region| region.end - region.start)
+            .sum::<usize>()
+    };
+    // After the first block is freed, its memory remains in use until the
+    // bump allocator can reclaim it.
+    assert_eq!(0, freed(&bump.frames[1]));
+    assert!(freed(&bump.frames[2]) >= 1000, "{:?}", bump.frames[2]);
+    assert_eq!(Some(4), bump.peak_frame());
+
+    // Free list allocator reuses memory of the first block.
+    let free_list = timeline(&trace, Strategy::FreeList, 4096).unwrap();
+    assert_eq!(0, freed(&free_list.frames[3]), "{:?}", free_list.frames[3]);
+}
+
+#[test]
+fn test_render() {
+    let region = |start, end, kind| Region { start, end, kind };
+    let frame = |event, end, regions: &[Region]| Frame {
+        event,
+        end,
+        regions: regions.to_vec(),
+    };
+    let header = region(0, 1024, Kind::Header);
+    let second = region(10240, 52288, Kind::Live);
+    let timeline = Timeline {
+        frames: vec![
+            frame(Event::Alloc { addr: 1024, size: 9216, align: 8 }, 10240, &[
+                header,
+                region(1024, 10240, Kind::Live),
+            ]),
+            frame(
+                Event::Alloc { addr: 10240, size: 42048, align: 8 },
+                52288,
+                &[header, region(1024, 10240, Kind::Live), second],
+            ),
+            frame(Event::Dealloc { addr: 1024 }, 52288, &[
+                header,
+                region(1024, 10240, Kind::Freed),
+                second,
+            ]),
+        ],
+        summary: Summary::default(),
+    };
+
+    // With 8 KiB per column, the 32 KiB boundary falls after fourth column.
+    let text = timeline.render_text(64 * 1024, 8);
+    let mut lines = text.lines();
+    assert!(lines.next().unwrap().starts_with("# 8192 bytes per column;"));
+    assert_eq!(
+        vec![
+            "    1 alloc 9216 @1024                   10240 ##  |    ",
+            "    2 alloc 42048 @10240                 52288 ####|###  <- peak",
+            "    3 dealloc @1024                      52288 .###|### ",
+        ],
+        lines.collect::<Vec<_>>()
+    );
+
+    let svg = timeline.render_svg(64 * 1024);
+    assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"), "{svg}");
+    assert_eq!(3, svg.matches("<g>").count());
+    assert!(svg.contains(">32 KiB</text>"), "{svg}");
+    assert!(svg.contains(">peak 52288</text>"), "{svg}");
+}
@@ -0,0 +1,80 @@
+//! Tests of the allocator running natively with Solana memory map reproduced
+//! by `mmap::map_heap`.
+//!
+//! Each scenario runs in a child process since the heap is a process-wide
+//! resource and some scenarios are expected to crash.
+
+#![cfg(all(
+    target_os = "linux",
+    feature = "sbf-memory-map",
+    not(feature = "testing")
+))]
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::os::unix::process::ExitStatusExt;
+
+use solana_allocator::{mmap, BumpAllocator};
+
+/// Environment variable with name of the scenario a child process runs.
+const SCENARIO: &str = "SOLANA_ALLOCATOR_SCENARIO";
+
+/// Runs given scenario in a child process and returns its exit status.
+fn run(test: &str, scenario: &str) -> std::process::ExitStatus {
+    std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture", "--test-threads=1"])
+        .env(SCENARIO, scenario)
+        .status()
+        .unwrap()
+}
+
+/// Returns whether the current process is a child running given scenario.
+fn is_child(scenario: &str) -> bool {
+    std::env::var(SCENARIO).is_ok_and(|var| var == scenario)
+}
+
+#[test]
+fn test_heap_at_fixed_address() {
+    if !is_child("fixed") {
+        assert!(run("test_heap_at_fixed_address", "fixed").success());
+        return;
+    }
+    mmap::map_heap(32 * 1024).unwrap();
+    let allocator = unsafe { BumpAllocator::<()>::with_heap_size(32 * 1024) };
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(0x3_0000_0000, ptr as usize & !0xffff);
+    // Memory is writable and, unless `debug-heap` fills new blocks,
+    // zero-initialised.
+    if !cfg!(feature = "debug-heap") {
+        assert_eq!(0, unsafe { ptr.read() });
+    }
+    unsafe { ptr.write_bytes(0xAA, 1024) };
+    // With known heap size, running out of memory is reported gracefully.
+    let layout = Layout::from_size_align(32 * 1024, 8).unwrap();
+    assert!(allocator.try_alloc(layout).is_err());
+    assert_eq!(
+        std::io::ErrorKind::AlreadyExists,
+        mmap::map_heap(4096).unwrap_err().kind()
+    );
+}
+
+#[test]
+fn test_out_of_memory_faults() {
+    if !is_child("fault") {
+        let status = run("test_out_of_memory_faults", "fault");
+        assert_eq!(Some(libc::SIGSEGV), status.signal(), "{status}");
+        return;
+    }
+    mmap::map_heap(32 * 1024).unwrap();
+    // Heap size is unknown so the allocator hands out memory past the mapping
+    // just like on Solana.
+    let allocator = unsafe { BumpAllocator::<()>::new() };
+    let layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    // With poke feature, allocation itself faults.
+    #[cfg(not(feature = "poke"))]
+    unsafe {
+        ptr.add(64 * 1024 - 1).write_volatile(1)
+    }
+    unreachable!("{ptr:?} is writable");
+}